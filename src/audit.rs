@@ -0,0 +1,257 @@
+use crate::error::Result;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+/// The environment variable that holds the path to the audit log
+pub const AUDIT_LOG_VAR: &str = "IMPOSTER_PASS_AUDIT_LOG";
+
+/// How a command ended
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Outcome {
+    Ok,
+    Error(String),
+}
+
+/// A single line in the audit log.
+///
+/// Records only ever describe which entries were touched. Secret values are never written to the
+/// log.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Record {
+    pub timestamp: DateTime<Utc>,
+
+    /// The pid of the imposter-pass process that ran the command
+    pub pid: u32,
+
+    /// The command line of the process that invoked imposter-pass, if it could be found
+    pub parent: Option<Vec<String>>,
+
+    /// The subcommand that was run, like `show` or `insert`
+    pub subcommand: String,
+
+    /// The password path the subcommand was run on
+    pub path: Option<PathBuf>,
+
+    pub result: Outcome,
+}
+
+impl Record {
+    pub fn new<T>(subcommand: &str, path: Option<&Path>, result: &Result<T>) -> Self {
+        Self {
+            timestamp: Utc::now(),
+            pid: std::process::id(),
+            parent: parent_command_line(),
+            subcommand: subcommand.to_string(),
+            path: path.map(Path::to_path_buf),
+            result: match result {
+                Ok(_) => Outcome::Ok,
+                Err(err) => Outcome::Error(err.to_string()),
+            },
+        }
+    }
+}
+
+/// Writes the outcome of a command to the audit log, if there is one, and passes the outcome on.
+///
+/// The command has already run by now, so failing to log it only gives a warning rather than
+/// replacing its outcome.
+pub fn record<T>(subcommand: &str, path: Option<&Path>, result: Result<T>) -> Result<T> {
+    if let Some(log) = Log::from_env() {
+        if let Err(err) = log.append(&Record::new(subcommand, path, &result)) {
+            eprintln!(
+                "Warning: {} was not written to the audit log at {:?}: {}",
+                subcommand,
+                log.path(),
+                err
+            );
+        }
+    }
+
+    result
+}
+
+/// Finds the command line of our parent process.
+///
+/// This only works where /proc is available.
+fn parent_command_line() -> Option<Vec<String>> {
+    #[cfg(target_family = "unix")]
+    {
+        let cmdline = std::fs::read(format!(
+            "/proc/{}/cmdline",
+            std::os::unix::process::parent_id()
+        ))
+        .ok()?;
+
+        Some(
+            cmdline
+                .split(|&byte| byte == 0)
+                .filter(|arg| !arg.is_empty())
+                .map(|arg| String::from_utf8_lossy(arg).into_owned())
+                .collect(),
+        )
+    }
+
+    #[cfg(not(target_family = "unix"))]
+    {
+        None
+    }
+}
+
+/// An append-only log of every command run through imposter-pass, stored as JSON lines
+#[derive(Debug, Clone)]
+pub struct Log {
+    path: PathBuf,
+}
+
+impl Log {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+
+    /// Opens the log named by IMPOSTER_PASS_AUDIT_LOG, if it is set
+    pub fn from_env() -> Option<Self> {
+        std::env::var_os(AUDIT_LOG_VAR).map(Self::new)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The current length of the log.
+    ///
+    /// This can be handed to [`Log::read_from`] later on to get just the records that were added
+    /// in the meantime.
+    pub fn len(&self) -> Result<u64> {
+        match std::fs::metadata(&self.path) {
+            Ok(metadata) => Ok(metadata.len()),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(0),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    pub fn append(&self, record: &Record) -> Result<()> {
+        use std::io::Write;
+
+        let mut line = serde_json::to_vec(record)?;
+        line.push(b'\n');
+
+        // The log names the entries that were used, so only its owner may read it
+        let mut options = std::fs::OpenOptions::new();
+        options.create(true).append(true);
+        #[cfg(target_family = "unix")]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+
+        // Each record goes out in a single write, so that concurrent writers can't interleave
+        options.open(&self.path)?.write_all(&line)?;
+
+        Ok(())
+    }
+
+    /// Reads every record that starts at or after the byte `offset`
+    pub fn read_from(&self, offset: u64) -> Result<Vec<Record>> {
+        use std::io::{BufRead, Seek, SeekFrom};
+
+        let mut file = match std::fs::File::open(&self.path) {
+            Ok(file) => file,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(err) => return Err(err.into()),
+        };
+        file.seek(SeekFrom::Start(offset))?;
+
+        std::io::BufReader::new(file)
+            .lines()
+            .filter(|line| line.as_ref().map(|line| !line.is_empty()).unwrap_or(true))
+            .map(|line| Ok(serde_json::from_str(&line?)?))
+            .collect()
+    }
+}
+
+/// A subcommand, the path it ran on, and how it ended
+type Access<'a> = (&'a str, Option<&'a Path>, &'a Outcome);
+
+/// Prints a summary of `records` to stderr
+pub fn report(program: &std::ffi::OsStr, records: &[Record]) {
+    use std::collections::BTreeMap;
+
+    eprintln!(
+        "\nimposter-pass report: {count} command(s) were run while fooling {program:?}",
+        count = records.len(),
+        program = program
+    );
+
+    // Group identical accesses together, so a program that reads the same entry in a loop
+    // doesn't drown out everything else
+    let mut summary: BTreeMap<Access, (usize, DateTime<Utc>)> = BTreeMap::new();
+    for record in records {
+        summary
+            .entry((
+                record.subcommand.as_str(),
+                record.path.as_deref(),
+                &record.result,
+            ))
+            .and_modify(|(count, last)| {
+                *count += 1;
+                *last = record.timestamp;
+            })
+            .or_insert((1, record.timestamp));
+    }
+
+    for ((subcommand, path, result), (count, last)) in summary {
+        let path = path
+            .map(|path| path.display().to_string())
+            .unwrap_or_else(|| "-".to_string());
+        let result = match result {
+            Outcome::Ok => "ok".to_string(),
+            Outcome::Error(message) => format!("error: {}", message),
+        };
+
+        eprintln!(
+            "  {subcommand:<21} {path:<32} {count:>4}x  last at {last}  {result}",
+            subcommand = subcommand,
+            path = path,
+            count = count,
+            last = last.to_rfc3339(),
+            result = result
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::Error;
+
+    #[test]
+    fn only_the_owner_can_read_the_log() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let path = std::env::temp_dir().join(format!("imposter-pass-audit-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let log = Log::new(&path);
+
+        log.append(&Record::new("show", Some(Path::new("a")), &Ok(())))
+            .unwrap();
+        log.append(&Record::new::<()>(
+            "insert",
+            Some(Path::new("a")),
+            &Err(Error::Declined("a".into())),
+        ))
+        .unwrap();
+
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        let records = log.read_from(0).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(mode & 0o777, 0o600);
+        assert_eq!(records[0].result, Outcome::Ok);
+        assert_eq!(
+            records[1].result,
+            Outcome::Error("a was left as it was".to_string())
+        );
+    }
+}
@@ -1,3 +1,4 @@
+use crate::personality::Personality;
 use std::path::PathBuf;
 use structopt::StructOpt;
 
@@ -12,6 +13,15 @@ pub enum Cmd {
         /// whole password store will be listed
         #[structopt(name = "pass-name", parse(from_os_str))]
         name: Option<PathBuf>,
+
+        /// Show which of the layered stores each entry comes from
+        #[structopt(long)]
+        explain: bool,
+
+        /// Show when each entry was created, changed and last read, what last changed it, and
+        /// how many times it has been read
+        #[structopt(long, short)]
+        long: bool,
     },
 
     /// Displays the value of a password.
@@ -23,6 +33,11 @@ pub enum Cmd {
         /// If this is not set, then this has the same output as imposter-pass ls
         #[structopt(name = "pass-name", help = "The password to show", parse(from_os_str))]
         name: Option<PathBuf>,
+
+        /// Show what is known about the entry instead of its value: when it was created,
+        /// changed and last read, what last changed it, and how many times it has been read
+        #[structopt(long, requires = "pass-name")]
+        meta: bool,
     },
 
     /// Inserts a new password value into the database.
@@ -45,6 +60,30 @@ pub enum Cmd {
         #[structopt(name = "force", long, short)]
         force: bool,
     },
+    /// Generates a new random password and inserts it into the database.
+    ///
+    /// This command tries to have the same behaviour as pass generate <PASS_NAME> [PASS_LENGTH]
+    Generate {
+        /// The path of the password you want to generate
+        #[structopt(name = "pass-name", parse(from_os_str))]
+        name: PathBuf,
+
+        /// The number of characters in the generated password
+        #[structopt(name = "pass-length", default_value = "25")]
+        length: usize,
+
+        /// This has the same behaviour as pass generate --no-symbols <PASS_NAME>
+        #[structopt(name = "no-symbols", long, short)]
+        no_symbols: bool,
+
+        /// This has the same behaviour as pass generate --in-place <PASS_NAME>
+        #[structopt(name = "in-place", long, short, conflicts_with = "force")]
+        in_place: bool,
+
+        /// This has the same behaviour as pass generate --force <PASS_NAME>
+        #[structopt(name = "force", long, short)]
+        force: bool,
+    },
     /// Removes an existing password value into the database.
     ///
     /// This command tries to have the same behaviour as pass insert <PASS_NAME>
@@ -67,29 +106,301 @@ pub enum Cmd {
         force: bool,
     },
     /// Executes the given program as though imposter-pass was the real pass
-    Fool {
-        /// The program you want to fool
-        ///
-        /// This program will be executed with a new entry added to its path. This new entry will
-        /// contain an exectuable pass, which is a wrapper around this imposter-pass exectuable
-        /// with some default settings applied
-        #[structopt(name = "program", parse(from_os_str))]
-        program: std::ffi::OsString,
-
-        /// Additional arguments you want to pass to the specified program
-        #[structopt(name = "arguments", parse(from_os_str))]
-        arguments: Vec<std::ffi::OsString>,
+    Fool(FoolOpts),
+
+    /// Serves the store as a freedesktop Secret Service (org.freedesktop.secrets)
+    ///
+    /// The service runs on the session bus from DBUS_SESSION_BUS_ADDRESS until the bus goes away.
+    /// Use fool --secret-service to run it on a private bus just for the fooled program.
+    #[structopt(name = "secret-service")]
+    SecretService,
+
+    /// Keeps the store in memory and serves it to clients on a Unix socket
+    ///
+    /// Clients are pointed at the server with --connect or IMPOSTER_PASS_SOCKET, and only send
+    /// back the entries they change. Changes are saved to the store as they are made, unless
+    /// the store is a json object, in which case they are only kept in memory. The server runs
+    /// until it is killed.
+    Serve {
+        /// The socket to listen on
+        #[structopt(long, parse(from_os_str))]
+        socket: PathBuf,
     },
+
+    /// Serves the store as a JSON API over HTTP, for test harnesses
+    ///
+    /// Entries can be listed, shown, inserted, removed and generated under /entries and
+    /// /generate. Every request must have an "Authorization: Bearer <token>" header. Each
+    /// request is checked against the policy and written to the audit log.
+    Http {
+        /// The address to listen on. Use port 0 to pick a free port, which is printed on startup.
+        #[structopt(long, default_value = "127.0.0.1:0")]
+        listen: String,
+
+        /// The bearer token that clients must send. If it isn't given, one is made up and
+        /// printed on startup.
+        #[structopt(long, env = "IMPOSTER_PASS_HTTP_TOKEN", hide_env_values = true)]
+        token: Option<String>,
+    },
+
+    /// Acts as a git credential helper, keeping credentials at git/<host>/<username>
+    ///
+    /// The first line of each entry is the password, and later "key: value" lines are extra
+    /// fields. Configure git with
+    /// `git config credential.helper '!imposter-pass --store store.json git-credential'`.
+    #[structopt(name = "git-credential")]
+    GitCredential {
+        /// The action git asks for: get, store or erase
+        action: String,
+    },
+
+    /// Fills in a template with entries from the store, and prints it
+    ///
+    /// Placeholders look like {{ pass "email/bridge" }}, which is replaced by the password of
+    /// that entry. Add line=N for the Nth line of the entry, or field=key for the value of a
+    /// "key: value" line. Rendering fails if any placeholder can't be filled in.
+    Render {
+        /// The template to render, or - to read it from stdin
+        #[structopt(parse(from_os_str))]
+        template: PathBuf,
+
+        /// Refuse to print the rendered template to a terminal
+        #[structopt(long)]
+        strict: bool,
+    },
+
+    /// Runs a command with entries from the store in its environment
+    ///
+    /// Each variable is set to the password (the first line) of an entry. For example,
+    /// `imposter-pass env --map DB_PASS=db/prod --prefix app/ -- cmd args` sets DB_PASS, and a
+    /// variable for every entry under app/, where app/db/user becomes DB_USER.
+    Env {
+        /// Sets the variable NAME to the entry at path, given as NAME=path
+        #[structopt(long, number_of_values = 1, parse(try_from_str = crate::env::parse_mapping))]
+        map: Vec<(String, PathBuf)>,
+
+        /// Sets a variable for every entry under this folder
+        #[structopt(long, number_of_values = 1, parse(from_os_str))]
+        prefix: Vec<PathBuf>,
+
+        /// Print the variables in .env format instead of running a command
+        #[structopt(long, conflicts_with = "command")]
+        dotenv: bool,
+
+        /// The command to run, and its arguments
+        #[structopt(name = "command", parse(from_os_str), required_unless = "dotenv")]
+        command: Vec<std::ffi::OsString>,
+    },
+
+    /// Checks that every store file has a MAC that matches it
+    ///
+    /// The key is read from IMPOSTER_PASS_MAC_KEY, or from the file at
+    /// IMPOSTER_PASS_MAC_KEY_FILE. This fails if there is no key, or if a store file is missing,
+    /// has no MAC, or was changed without the key, so it can be used as a CI check.
+    Verify {
+        /// Adds a MAC to store files that don't have one yet, which is how a MAC is first added
+        /// to an existing store. Files that already have one are only checked.
+        #[structopt(long)]
+        sign: bool,
+    },
+
+    /// Prints the store encrypted with a passphrase, to be given to IMPOSTER_PASS_STORE
+    ///
+    /// The passphrase is read from IMPOSTER_PASS_PASSPHRASE, or from the file at
+    /// IMPOSTER_PASS_PASSPHRASE_FILE, or asked for if neither is set. Stores sealed this way are
+    /// decrypted with the same variables when they are loaded.
+    Seal,
+
+    /// Rewrites every store file in the current version of the store format
+    ///
+    /// Store files in older versions can still be read, but are saved in the version they were
+    /// loaded in, so that older versions of imposter-pass can still read them. That means that
+    /// what is known about their entries (see ls --long) isn't kept until they are migrated. Run
+    /// this once nothing needs them to.
+    Migrate,
 }
 
 #[derive(Debug, StructOpt)]
-#[structopt(name = "imposter-pass")]
-pub struct Cli {
+pub struct FoolOpts {
+    /// The program you want to fool
+    ///
+    /// This program will be executed with a new entry added to its path. This new entry will
+    /// contain an exectuable pass, which is a wrapper around this imposter-pass exectuable
+    /// with some default settings applied
+    #[structopt(name = "program", parse(from_os_str))]
+    pub program: std::ffi::OsString,
+
+    /// Additional arguments you want to pass to the specified program
+    #[structopt(name = "arguments", parse(from_os_str))]
+    pub arguments: Vec<std::ffi::OsString>,
+
+    /// The programs to impersonate
+    ///
+    /// Each of these is put on the path of the fooled program, and works on the same store.
+    #[structopt(
+        name = "shim",
+        long,
+        default_value = "pass",
+        possible_values = &Personality::SHIMS,
+        use_delimiter = true
+    )]
+    pub shims: Vec<Personality>,
+
+    /// Print a summary of every entry the program accessed once it exits
+    ///
+    /// If IMPOSTER_PASS_AUDIT_LOG is not set, a temporary audit log is used for this run.
+    #[structopt(name = "report", long)]
+    pub report: bool,
+
+    /// Only allow the program to read from the store
+    ///
+    /// This can be combined with --policy to restrict which entries may be read.
+    #[structopt(name = "read-only", long)]
+    pub read_only: bool,
+
+    /// Record every call the program makes to pass into this session file
+    ///
+    /// The arguments, input, output and exit code of each call are saved, so that they can be
+    /// played back later with --replay.
+    #[structopt(name = "record", long, parse(from_os_str), conflicts_with = "replay")]
+    pub record: Option<PathBuf>,
+
+    /// Answer calls to pass from a session file made with --record
+    ///
+    /// No store is read or written. Calls that weren't recorded fail, and cause fool to fail
+    /// once the program exits.
+    #[structopt(name = "replay", long, parse(from_os_str))]
+    pub replay: Option<PathBuf>,
+
+    /// Start a private D-Bus session bus with a Secret Service backed by the store on it
+    ///
+    /// The program is pointed at the private bus with DBUS_SESSION_BUS_ADDRESS. This needs
+    /// dbus-daemon to be installed.
+    #[structopt(name = "secret-service", long)]
+    pub secret_service: bool,
+
+    /// Lay the store out as files in a temporary directory, and point PASSWORD_STORE_DIR at it
+    ///
+    /// This is for programs that look for .gpg-id or list the .gpg files instead of calling pass.
+    /// Each file holds its entry unencrypted. Files that the program changes, adds or removes
+    /// are synced back into the store once it exits.
+    #[structopt(name = "tree", long)]
+    pub tree: bool,
+
+    /// Serve the store from memory while the program runs, rather than from a temporary file
+    ///
+    /// Every shim becomes a thin client of a server like `imposter-pass serve`, so calls are
+    /// cheaper, and calls made at the same time can't undo each other's changes.
+    #[structopt(name = "serve", long)]
+    pub serve: bool,
+}
+
+impl Cmd {
+    /// The name of the subcommand, as it is written on the command line
+    pub fn name(&self) -> &'static str {
+        match self {
+            Cmd::List { .. } => "ls",
+            Cmd::Show { .. } => "show",
+            Cmd::Insert { .. } => "insert",
+            Cmd::Generate { .. } => "generate",
+            Cmd::Remove { .. } => "rm",
+            Cmd::Fool(_) => "fool",
+            Cmd::SecretService => "secret-service",
+            Cmd::Serve { .. } => "serve",
+            Cmd::Http { .. } => "http",
+            Cmd::GitCredential { .. } => "git-credential",
+            Cmd::Render { .. } => "render",
+            Cmd::Env { .. } => "env",
+            Cmd::Verify { .. } => "verify",
+            Cmd::Seal => "seal",
+            Cmd::Migrate => "migrate",
+        }
+    }
+
+    /// Whether the subcommand might read stdin, for the value of an entry or to ask before
+    /// changing one
+    pub fn reads_stdin(&self) -> bool {
+        match self {
+            Cmd::Insert {
+                echo,
+                multiline,
+                force,
+                ..
+            } => *echo || *multiline || !*force,
+            Cmd::Generate {
+                in_place, force, ..
+            } => !*in_place && !*force,
+            Cmd::Remove { force, .. } => !*force,
+            Cmd::Render { template, .. } => template == "-",
+            Cmd::Fool(_) | Cmd::GitCredential { .. } | Cmd::Env { .. } => true,
+            Cmd::List { .. }
+            | Cmd::Show { .. }
+            | Cmd::SecretService
+            | Cmd::Serve { .. }
+            | Cmd::Http { .. }
+            | Cmd::Verify { .. }
+            | Cmd::Seal
+            | Cmd::Migrate => false,
+        }
+    }
+
+    /// The password path that the subcommand works on, if it has one
+    pub fn pass_name(&self) -> Option<&std::path::Path> {
+        match self {
+            Cmd::List { name, .. } | Cmd::Show { name, .. } => name.as_deref(),
+            Cmd::Insert { name, .. } | Cmd::Generate { name, .. } | Cmd::Remove { name, .. } => {
+                Some(name)
+            }
+            Cmd::Fool(_)
+            | Cmd::SecretService
+            | Cmd::Serve { .. }
+            | Cmd::Http { .. }
+            | Cmd::GitCredential { .. }
+            | Cmd::Render { .. }
+            | Cmd::Env { .. }
+            | Cmd::Verify { .. }
+            | Cmd::Seal
+            | Cmd::Migrate => None,
+        }
+    }
+}
+
+/// Options for finding the store, shared by all of the programs imposter-pass can impersonate
+#[derive(Debug, StructOpt)]
+pub struct StoreOpts {
     /// The password store to use.
     ///
     /// This may be a json object mapping password paths to values, or it may be a file path to use for the password store
-    #[structopt(long, env = "IMPOSTER_PASS_STORE")]
-    pub store: Option<String>,
+    ///
+    /// Give this more than once (or give a json array of stores) to layer the stores. Entries are
+    /// looked up in each store in turn, and changes are saved to the first file.
+    #[structopt(long, env = "IMPOSTER_PASS_STORE", number_of_values = 1)]
+    pub store: Vec<String>,
+
+    /// Use the store served by `imposter-pass serve` on this socket, instead of --store
+    #[structopt(long, env = "IMPOSTER_PASS_SOCKET", parse(from_os_str))]
+    pub connect: Option<PathBuf>,
+
+    /// A policy file restricting which entries may be shown, inserted, removed or generated.
+    ///
+    /// Denied entries are hidden from ls and show, and changing them fails with a permission
+    /// error. The fool command passes the policy on to the program it runs.
+    #[structopt(long, env = "IMPOSTER_PASS_POLICY", parse(from_os_str))]
+    pub policy: Option<PathBuf>,
+
+    /// What to do if the store could be read or changed by other users or processes: warn,
+    /// refuse to use it (strict), or don't check at all (off)
+    ///
+    /// This checks for store files that other users can read, directories that other users can
+    /// write to, stores in shared temporary directories, and secrets given on the command line or
+    /// in the environment.
+    #[structopt(
+        long,
+        env = "IMPOSTER_PASS_SECURITY",
+        default_value = "warn",
+        possible_values = &crate::security::Mode::NAMES
+    )]
+    pub security: crate::security::Mode,
 
     /// Indicates that imposter pass should avoid showing information in addition to what the real
     /// pass would have output
@@ -102,6 +413,142 @@ pub struct Cli {
     #[structopt(short, long, env = "IMPOSTER_PASS_QUIET")]
     pub quiet: bool,
 
+    /// Count each read of an entry in the store, along with when it was last read
+    ///
+    /// This saves the store after every read, so it is off by default. The counts are shown by
+    /// `ls --long` and `show --meta`. Setting IMPOSTER_PASS_TRACK_READS to `true` does the same.
+    #[structopt(long)]
+    pub track_reads: bool,
+
+    /// Show the values of the entries that changed, when telling you what was changed in the
+    /// store
+    ///
+    /// The values are masked by default, so that secrets don't end up in logs.
+    #[structopt(long)]
+    pub show_secrets: bool,
+}
+
+/// The subcommands that the real pass has
+const PASS_COMMANDS: &[&str] = &["ls", "show", "insert", "generate", "rm", "help"];
+
+#[derive(Debug, StructOpt)]
+#[structopt(name = "imposter-pass")]
+pub struct Cli {
+    #[structopt(flatten)]
+    pub opts: StoreOpts,
+
     #[structopt(subcommand)]
     pub cmd: Cmd,
 }
+
+impl Cli {
+    /// Parses the command line the way pass would.
+    ///
+    /// Just like pass, running without a subcommand lists the store, and giving a password name
+    /// in place of a subcommand shows that password. Quiet mode is always enabled, since a
+    /// program calling pass doesn't expect any extra output.
+    pub fn from_pass_args<I>(args: I) -> Self
+    where
+        I: IntoIterator,
+        I::Item: Into<std::ffi::OsString>,
+    {
+        Self::try_from_pass_args(args).unwrap_or_else(|err| err.exit())
+    }
+
+    /// Like [`Cli::from_pass_args`], but gives back parse errors (and `--help`) rather than
+    /// exiting
+    pub fn try_from_pass_args<I>(args: I) -> structopt::clap::Result<Self>
+    where
+        I: IntoIterator,
+        I::Item: Into<std::ffi::OsString>,
+    {
+        use structopt::clap::ErrorKind;
+
+        let mut args: Vec<std::ffi::OsString> = args.into_iter().map(Into::into).collect();
+
+        // Only pass's own subcommands are subcommands to pass, so that entries named like one of
+        // ours (env, serve, ...) are shown by `pass <name>` just like with the real pass
+        if let Some(index) = args
+            .iter()
+            .skip(1)
+            .position(|arg| !arg.to_string_lossy().starts_with('-'))
+        {
+            if !PASS_COMMANDS
+                .iter()
+                .any(|command| args[index + 1] == *command)
+            {
+                args.insert(index + 1, "show".into());
+            }
+        }
+
+        let mut cli = match Self::from_iter_safe(&args) {
+            Ok(cli) => cli,
+            Err(err) => match err.kind {
+                ErrorKind::MissingArgumentOrSubcommand => {
+                    args.push("ls".into());
+                    Self::from_iter_safe(args)?
+                }
+                ErrorKind::UnrecognizedSubcommand | ErrorKind::UnknownArgument => {
+                    // Treat the first argument that isn't a flag as a password name
+                    let name_index = args
+                        .iter()
+                        .skip(1)
+                        .position(|arg| !arg.to_string_lossy().starts_with('-'))
+                        .map(|index| index + 1)
+                        .unwrap_or_else(|| args.len());
+
+                    args.insert(name_index, "show".into());
+                    Self::from_iter_safe(args)?
+                }
+                _ => return Err(err),
+            },
+        };
+
+        cli.opts.quiet = true;
+        Ok(cli)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn shown(args: &[&str]) -> Option<PathBuf> {
+        match Cli::from_pass_args(args).cmd {
+            Cmd::Show { name, .. } => name,
+            cmd => panic!("{:?} parsed as {}", args, cmd.name()),
+        }
+    }
+
+    #[test]
+    fn pass_name_in_place_of_subcommand() {
+        assert_eq!(
+            shown(&["pass", "web/github.com"]),
+            Some("web/github.com".into())
+        );
+        assert_eq!(shown(&["pass", "show", "web"]), Some("web".into()));
+    }
+
+    #[test]
+    fn pass_names_like_our_subcommands() {
+        for name in &[
+            "env", "serve", "http", "render", "verify", "seal", "migrate", "fool",
+        ] {
+            assert_eq!(shown(&["pass", name]), Some(name.into()));
+        }
+    }
+
+    #[test]
+    fn pass_subcommands() {
+        assert_eq!(Cli::from_pass_args(&["pass"]).cmd.name(), "ls");
+        assert_eq!(Cli::from_pass_args(&["pass", "rm", "x"]).cmd.name(), "rm");
+        assert!(Cli::from_pass_args(&["pass", "ls"]).opts.quiet);
+    }
+
+    #[test]
+    fn track_reads_is_a_flag() {
+        let cli = Cli::from_iter_safe(&["imposter-pass", "--track-reads", "show", "a"]).unwrap();
+        assert!(cli.opts.track_reads);
+        assert_eq!(shown(&["pass", "--track-reads", "a"]), Some("a".into()));
+    }
+}
@@ -0,0 +1,408 @@
+//! The implementations of the pass subcommands, shared by every program imposter-pass can
+//! impersonate
+
+use crate::cli::Cmd;
+use crate::context::Context;
+use crate::error::{Error, Result};
+use crate::layers;
+use crate::meta;
+use crate::policy::Operation;
+use crate::secret::Secret;
+use crate::source::Source;
+use crate::store::{self, Diff, Store};
+use std::io::prelude::*;
+use std::path::Path;
+
+/// Asks the user a yes or no question on stdout, and checks the reply against `expected`
+pub fn check(msg: &str, expected: &str) -> Result<bool> {
+    print!("{}", msg);
+    std::io::stdout().flush()?;
+
+    let mut reply = String::new();
+    std::io::stdin().read_line(&mut reply)?;
+
+    Ok(reply.trim_end_matches('\n').eq_ignore_ascii_case(expected))
+}
+
+/// Asks before replacing an existing entry, and fails if it may not be replaced
+fn overwrite_check(ctx: &Context, name: &Path) -> Result<()> {
+    let confirmed = match ctx.store.get(name)? {
+        None => true,
+        Some(_) => check(
+            &format!(
+                "An entry already exists for {}. Overwrite it? [y/N] ",
+                name.display()
+            ),
+            "y",
+        )?,
+    };
+
+    if !confirmed {
+        return Err(Error::Declined(name.to_path_buf()));
+    }
+
+    Ok(())
+}
+
+/// Generates a random password of `length` characters.
+///
+/// Just like pass, the password is made of letters and digits, plus punctuation if `symbols` is
+/// set.
+pub fn generate_password(length: usize, symbols: bool) -> Secret {
+    use rand::seq::SliceRandom;
+
+    let characters: Vec<u8> = (b'!'..=b'~')
+        .filter(|c| symbols || c.is_ascii_alphanumeric())
+        .collect();
+    let mut rng = rand::rngs::OsRng;
+
+    let mut password = String::with_capacity(length);
+    for _ in 0..length {
+        password.push(
+            *characters
+                .choose(&mut rng)
+                .expect("character set is not empty") as char,
+        );
+    }
+
+    password.into()
+}
+
+/// Runs one of the imposter-pass subcommands
+pub fn run(ctx: &Context, cmd: &Cmd) -> Result<()> {
+    match cmd {
+        Cmd::List {
+            name,
+            explain,
+            long,
+        } if *explain || *long => details(ctx, name.as_deref(), *explain, *long),
+        Cmd::Show {
+            name: Some(name),
+            meta: true,
+        } => show_meta(ctx, name),
+        Cmd::List { name, .. } | Cmd::Show { name, .. } => show(ctx, name.as_deref()),
+        Cmd::Insert {
+            name,
+            echo,
+            multiline,
+            force,
+        } => insert(ctx, name, *echo, *multiline, *force),
+        Cmd::Generate {
+            name,
+            length,
+            no_symbols,
+            in_place,
+            force,
+        } => generate(ctx, name, *length, !no_symbols, *in_place, *force),
+        Cmd::Remove {
+            name,
+            recursive,
+            force,
+        } => remove(ctx, name, *recursive, *force),
+        Cmd::Fool(opts) => crate::fool::fool(ctx, opts),
+        Cmd::SecretService => crate::secret_service::serve(ctx),
+        Cmd::Serve { socket } => crate::serve::Server::bind(socket, ctx.clone())?
+            .run()
+            .map(drop),
+        Cmd::Http { listen, token } => crate::http::serve(ctx, listen, token.as_deref()),
+        Cmd::GitCredential { action } => crate::git_credential::run(ctx, action),
+        Cmd::Render { template, strict } => crate::render::render(ctx, template, *strict),
+        Cmd::Env {
+            map,
+            prefix,
+            dotenv,
+            command,
+        } => crate::env::env(ctx, map, prefix, *dotenv, command),
+        Cmd::Verify { .. } => crate::integrity::verify(ctx),
+        Cmd::Seal => crate::seal::run(ctx),
+        Cmd::Migrate => migrate(ctx),
+    }
+}
+
+/// Shows an entry, or lists the entries under a folder
+pub fn show(ctx: &Context, name: Option<&Path>) -> Result<()> {
+    let visible = ctx.policy.visible(&ctx.store)?;
+    visible.show(name)?;
+
+    // Listing a folder doesn't hand out any values
+    match name {
+        Some(name)
+            if visible.get(name)?.is_some() && visible.list_paths(Some(name))?.count() == 1 =>
+        {
+            ctx.record_reads([name]);
+        }
+        _ => {}
+    }
+
+    Ok(())
+}
+
+/// Lists the entries under a folder in columns, with the layer each one comes from if `explain`
+/// is set, and what is known about it if `long` is set
+pub fn details(ctx: &Context, name: Option<&Path>, explain: bool, long: bool) -> Result<()> {
+    let visible = ctx.policy.visible(&ctx.store)?;
+    let entries: Vec<_> = visible.list_entries(name)?.collect();
+    if let (Some(name), []) = (name, entries.as_slice()) {
+        return Err(Error::NotInStore(name.to_path_buf()));
+    }
+
+    let mut rows = Vec::new();
+    if long {
+        let mut header = vec!["NAME"];
+        if explain {
+            header.push("LAYER");
+        }
+        header.extend(["CREATED", "UPDATED", "READS", "LAST READ", "WRITER"]);
+        rows.push(header.into_iter().map(str::to_string).collect());
+    }
+    for (path, entry) in entries {
+        let mut row = vec![path.display().to_string()];
+        if explain {
+            row.push(match (layers::origin(&ctx.layers, path)?, &ctx.socket) {
+                (Some(layer), _) => layer.source.to_string(),
+                (None, Some(socket)) => socket.display().to_string(),
+                (None, None) => "(memory)".to_string(),
+            });
+        }
+        if long {
+            let meta = &entry.meta;
+            row.extend([
+                meta::format_time(meta.created),
+                meta::format_time(meta.updated),
+                meta.reads.to_string(),
+                meta::format_time(meta.last_read),
+                meta.writer.clone().unwrap_or_else(|| "-".to_string()),
+            ]);
+        }
+        rows.push(row);
+    }
+
+    print_columns(&rows);
+    Ok(())
+}
+
+/// Prints `rows` with each column lined up
+fn print_columns(rows: &[Vec<String>]) {
+    let mut widths: Vec<usize> = Vec::new();
+    for row in rows {
+        widths.resize(widths.len().max(row.len()), 0);
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+
+    for row in rows {
+        let mut line = String::new();
+        for (i, (cell, width)) in row.iter().zip(&widths).enumerate() {
+            if i + 1 == row.len() {
+                line.push_str(cell);
+            } else {
+                line.push_str(&format!("{:width$}  ", cell, width = width));
+            }
+        }
+        println!("{}", line);
+    }
+}
+
+/// Prints what is known about an entry, without its value
+pub fn show_meta(ctx: &Context, name: &Path) -> Result<()> {
+    let visible = ctx.policy.visible(&ctx.store)?;
+    let meta = match visible.meta(name)? {
+        Some(meta) => meta,
+        None if visible.list(Some(name))?.next().is_some() => {
+            return Err(Error::Usage(format!("{} is a folder", name.display())))
+        }
+        None => return Err(Error::NotInStore(name.to_path_buf())),
+    };
+
+    println!("created:   {}", meta::format_time(meta.created));
+    println!("updated:   {}", meta::format_time(meta.updated));
+    println!("writer:    {}", meta.writer.as_deref().unwrap_or("-"));
+    println!("reads:     {}", meta.reads);
+    println!("last read: {}", meta::format_time(meta.last_read));
+
+    Ok(())
+}
+
+/// Sets the value of an entry, without asking any questions
+pub fn set(ctx: &Context, name: &Path, value: Secret) -> Result<()> {
+    ctx.policy.check(Operation::Insert, name)?;
+
+    let mut diff = Diff::default();
+    diff.set.insert(Store::normalize(name)?, value);
+
+    ctx.apply(diff)
+}
+
+/// Prompts for the value of an entry, and inserts it
+pub fn insert(ctx: &Context, name: &Path, echo: bool, multiline: bool, force: bool) -> Result<()> {
+    ctx.policy.check(Operation::Insert, name)?;
+
+    if !force {
+        overwrite_check(ctx, name)?;
+    }
+
+    let password = if echo {
+        print!("Enter password for {}: ", name.display());
+        std::io::stdout().flush()?;
+        Secret::read_line(&mut std::io::stdin().lock())?
+    } else if multiline {
+        println!(
+            "Enter contents of {} and press Ctrl+D when finished:\n",
+            name.display()
+        );
+        std::io::stdout().flush()?;
+
+        Secret::read_to_end(&mut std::io::stdin())?
+    } else {
+        let password = Secret::from(rpassword::read_password_from_tty(Some(&format!(
+            "Enter password for {}: ",
+            name.display()
+        )))?);
+        let retype_password = Secret::from(rpassword::read_password_from_tty(Some(&format!(
+            "Retype password for {}: ",
+            name.display()
+        )))?);
+
+        if password != retype_password {
+            eprintln!("Error: the entered passwords do not match.");
+            return Ok(());
+        }
+
+        password
+    };
+
+    set(ctx, name, password)
+}
+
+/// Generates a new password for an entry
+pub fn generate(
+    ctx: &Context,
+    name: &Path,
+    length: usize,
+    symbols: bool,
+    in_place: bool,
+    force: bool,
+) -> Result<()> {
+    ctx.policy.check(Operation::Generate, name)?;
+
+    if !force && !in_place {
+        overwrite_check(ctx, name)?;
+    }
+
+    let password = generate_password(length, symbols);
+
+    println!(
+        "The generated password for {} is:\n{}",
+        name.display(),
+        &*password
+    );
+
+    let mut store = ctx.store.clone();
+    set_password(&mut store, name, password, in_place)?;
+
+    ctx.save(&store)
+}
+
+/// Sets the password of an entry. If `in_place` is set, only the first line is replaced.
+pub fn set_password(
+    store: &mut Store,
+    name: &Path,
+    password: Secret,
+    in_place: bool,
+) -> Result<()> {
+    let value = store.value_mut(name)?;
+    *value = if in_place {
+        // Only the first line is the password, anything after it is kept as is
+        match value.find('\n') {
+            Some(end) => Secret::concat(&[&password, &value[end..]]),
+            None => password,
+        }
+    } else {
+        password
+    };
+
+    Ok(())
+}
+
+/// Fails if the entry (or folder, if `recursive` is set) at `name` can't be removed
+pub fn check_remove(ctx: &Context, name: &Path, recursive: bool) -> Result<()> {
+    // Entries hidden by the policy behave as if they don't exist
+    let count = ctx.policy.visible(&ctx.store)?.list(Some(name))?.count();
+    if count == 0 {
+        return Err(Error::NotInStore(name.to_path_buf()));
+    }
+
+    ctx.policy.check_all(Operation::Rm, &ctx.store, name)?;
+
+    if count > 1 && !recursive {
+        return Err(Error::IsADirectory(name.to_path_buf()));
+    }
+
+    Ok(())
+}
+
+/// Removes an entry, or a folder of entries if `recursive` is set
+pub fn remove(ctx: &Context, name: &Path, recursive: bool, force: bool) -> Result<()> {
+    check_remove(ctx, name, recursive)?;
+
+    let confirmed = force
+        || check(
+            &format!(
+                "Are you sure you would like to delete {}? [y/N] ",
+                name.display()
+            ),
+            "y",
+        )?;
+
+    if !confirmed {
+        return Err(Error::Declined(name.to_path_buf()));
+    }
+
+    let diff = Diff {
+        removed: ctx
+            .store
+            .list_paths(Some(name))?
+            .map(Path::to_path_buf)
+            .collect(),
+        ..Diff::default()
+    };
+    ctx.apply(diff)
+}
+
+/// Saves every store file in the current version of the store format
+pub fn migrate(ctx: &Context) -> Result<()> {
+    let sources: Vec<&Source> = ctx
+        .layers
+        .iter()
+        .map(|layer| &layer.source)
+        .filter(|source| source.is_writable())
+        .collect();
+    if sources.is_empty() {
+        return Err(Error::Usage(
+            "there is no store file to migrate".to_string(),
+        ));
+    }
+
+    for source in sources {
+        let _lock = source.lock()?;
+        let mut store = source.load()?;
+
+        let version = store.version();
+        if version == store::VERSION {
+            println!("{}: already version {}", source, version);
+            continue;
+        }
+
+        store.migrate();
+        source.save(&store, true)?;
+        println!(
+            "{}: migrated from version {} to {}",
+            source,
+            version,
+            store::VERSION
+        );
+    }
+
+    Ok(())
+}
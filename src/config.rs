@@ -0,0 +1,50 @@
+use crate::error::Result;
+use serde::Deserialize;
+use std::path::PathBuf;
+
+/// Settings that are read from the imposter-pass config file.
+///
+/// This is mostly useful when imposter-pass is installed as `pass`, since in that case there is
+/// nobody around to give it a `--store` on the command line.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// The password store to use when neither `--store` nor IMPOSTER_PASS_STORE are set.
+    ///
+    /// This accepts the same values as `--store`, or a list of them to layer the stores.
+    pub store: Option<serde_json::Value>,
+}
+
+impl Config {
+    /// The location of the config file.
+    ///
+    /// This is IMPOSTER_PASS_CONFIG if it is set, and otherwise
+    /// `$XDG_CONFIG_HOME/imposter-pass/config.json` (falling back to `~/.config`).
+    pub fn path() -> Option<PathBuf> {
+        if let Some(path) = std::env::var_os("IMPOSTER_PASS_CONFIG") {
+            return Some(PathBuf::from(path));
+        }
+
+        let config_home = std::env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+
+        Some(config_home.join("imposter-pass").join("config.json"))
+    }
+
+    /// The store setting, written the way it would be given to `--store`
+    pub fn store_opt(&self) -> Option<String> {
+        self.store.as_ref().map(|store| match store.as_str() {
+            Some(store) => store.to_string(),
+            None => store.to_string(),
+        })
+    }
+
+    /// Loads the config file, or returns the default config if there isn't one
+    pub fn load() -> Result<Self> {
+        match Self::path() {
+            Some(path) if path.exists() => Ok(serde_json::from_reader(std::fs::File::open(path)?)?),
+            _ => Ok(Self::default()),
+        }
+    }
+}
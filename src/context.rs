@@ -0,0 +1,237 @@
+use crate::cli::StoreOpts;
+use crate::config::Config;
+use crate::error::Result;
+use crate::layers::{self, Layer};
+use crate::meta::Reads;
+use crate::policy::Policy;
+use crate::security;
+use crate::serve;
+use crate::source::Source;
+use crate::store::{Diff, Store};
+use chrono::Utc;
+use std::path::{Path, PathBuf};
+
+/// If this environment variable is set to `true`, reads are counted as if --track-reads was given
+pub const TRACK_READS_VAR: &str = "IMPOSTER_PASS_TRACK_READS";
+
+/// Whether reads should be counted, from --track-reads or IMPOSTER_PASS_TRACK_READS
+fn track_reads(opts: &StoreOpts) -> bool {
+    opts.track_reads
+        || std::env::var(TRACK_READS_VAR)
+            .map(|value| value.eq_ignore_ascii_case("true"))
+            .unwrap_or(false)
+}
+
+/// The password store that a command works on, along with everything needed to save changes to
+/// it
+#[derive(Debug, Clone)]
+pub struct Context {
+    pub quiet: bool,
+
+    /// Whether the values of changed entries are shown when the changes are printed
+    pub show_secrets: bool,
+
+    /// The store as it was when it was loaded
+    pub store: Store,
+
+    /// Where the store was loaded from, in the order entries are looked up. Changes are saved
+    /// to the first of these that can be written to.
+    pub layers: Vec<Layer>,
+
+    /// The socket of the server the store was loaded from. If this is set, changes are sent to
+    /// the server instead of being saved to `source`.
+    pub socket: Option<PathBuf>,
+
+    pub policy: Policy,
+
+    /// Where the policy was loaded from, so that it can be passed on to fooled programs
+    pub policy_path: Option<PathBuf>,
+
+    /// The command making the changes, which is kept with each entry it sets
+    pub writer: Option<String>,
+
+    /// Whether reads of entries are counted in the store
+    pub track_reads: bool,
+}
+
+impl Context {
+    /// Loads the store and policy described by `opts`.
+    ///
+    /// If no store was given, the store from the config file is used.
+    pub fn load(opts: &StoreOpts) -> Result<Self> {
+        if let Some(socket) = &opts.connect {
+            if !opts.quiet {
+                eprintln!("BEWARE! THIS IS NOT THE REAL PASS (https://www.passwordstore.org/), BUT ONLY A CLEVER IMPOSTER!");
+                eprintln!("Warning: database will be loaded from and saved to the server at {:?}. THE DATABASE IS NOT ENCRYPTED!", socket);
+            }
+
+            return Ok(Self {
+                quiet: opts.quiet,
+                show_secrets: opts.show_secrets,
+                store: serve::load(socket)?,
+                layers: Vec::new(),
+                socket: Some(socket.clone()),
+                policy: Policy::load(opts.policy.as_deref())?,
+                policy_path: opts.policy.clone(),
+                writer: None,
+                track_reads: track_reads(opts),
+            });
+        }
+
+        let sources = Self::sources(opts)?;
+
+        if !opts.quiet {
+            eprintln!("BEWARE! THIS IS NOT THE REAL PASS (https://www.passwordstore.org/), BUT ONLY A CLEVER IMPOSTER!");
+            eprintln!("If you did not expect to see this message, STOP doing whatever you're doing! You've been bamboozeled!");
+            match sources.as_slice() {
+                [] => eprintln!("Warning: no database specified. Defaulting to empty database. Changes WILL NOT BE SAVED!"),
+                [source] => source.warn(),
+                sources => {
+                    for source in sources {
+                        source.warn();
+                    }
+                    eprintln!("Warning: entries will be looked up in the databases in the order they were given, and changes saved to the first file.");
+                }
+            }
+        }
+
+        security::check(opts.security, opts.quiet, &sources, !opts.store.is_empty())?;
+
+        let layers = sources
+            .into_iter()
+            .map(Layer::load)
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Self {
+            quiet: opts.quiet,
+            show_secrets: opts.show_secrets,
+            store: layers::merge(&layers)?,
+            layers,
+            socket: None,
+            policy: Policy::load(opts.policy.as_deref())?,
+            policy_path: opts.policy.clone(),
+            writer: None,
+            track_reads: track_reads(opts),
+        })
+    }
+
+    /// Where the store described by `opts` is loaded from, without loading it.
+    ///
+    /// If no store was given, the store from the config file is used.
+    pub fn sources(opts: &StoreOpts) -> Result<Vec<Source>> {
+        let store_opts = if opts.store.is_empty() {
+            Config::load()?.store_opt().into_iter().collect()
+        } else {
+            opts.store.clone()
+        };
+
+        let mut sources = Vec::new();
+        for store_opt in &store_opts {
+            sources.extend(Source::parse(store_opt)?);
+        }
+
+        Ok(sources)
+    }
+
+    /// Sets the command that is making the changes, like `pass insert`
+    pub fn written_by(mut self, writer: impl Into<String>) -> Self {
+        self.writer = Some(writer.into());
+        self
+    }
+
+    /// Reads the store again, to pick up changes that other processes have made since it was
+    /// loaded
+    pub fn reload(&mut self) -> Result<()> {
+        if let Some(socket) = &self.socket {
+            self.store = serve::load(socket)?;
+        } else if self.layers.iter().any(|layer| layer.source.is_writable()) {
+            // Other sources can't be changed by anyone else, and reloading them would throw
+            // away the changes that are only kept in memory
+            for layer in &mut self.layers {
+                layer.reload()?;
+            }
+            self.store = layers::merge(&self.layers)?;
+        }
+
+        Ok(())
+    }
+
+    /// Replaces the loaded store with `new_store`
+    pub fn save(&self, new_store: &Store) -> Result<()> {
+        self.apply(self.store.diff(new_store))
+    }
+
+    /// Makes the changes in `diff` to the loaded store, without needing a copy of the whole
+    /// store to make them in
+    pub fn apply(&self, mut diff: Diff) -> Result<()> {
+        if diff.is_empty() {
+            return Ok(());
+        }
+
+        if diff.writer.is_none() {
+            diff.writer = self.writer.clone();
+        }
+        if !self.quiet && diff.changes_values() {
+            self.print_changes(&diff);
+        }
+
+        if let Some(socket) = &self.socket {
+            serve::request(socket, &serve::Request::Apply { diff })?;
+        } else {
+            layers::save(&self.layers, &diff)?;
+        }
+
+        Ok(())
+    }
+
+    /// Counts a read of each entry in `paths`, once its value has been handed out, if reads are
+    /// tracked.
+    ///
+    /// The value has been read either way, so failing to count the read only gives a warning.
+    pub fn record_reads<'a>(&self, paths: impl IntoIterator<Item = &'a Path>) {
+        if !self.track_reads {
+            return;
+        }
+
+        let last = Utc::now();
+        let mut diff = Diff::default();
+        for path in paths {
+            if let Ok(path) = Store::normalize(path) {
+                diff.read
+                    .entry(path)
+                    .or_insert(Reads { count: 0, last })
+                    .count += 1;
+            }
+        }
+
+        if let Err(err) = self.apply(diff) {
+            if !self.quiet {
+                eprintln!("Warning: the read was not counted: {}", err);
+            }
+        }
+    }
+
+    /// Prints the paths that `diff` adds, changes and removes, masking the values unless
+    /// `show_secrets` is set
+    fn print_changes(&self, diff: &Diff) {
+        let value = |value: &str| {
+            if self.show_secrets {
+                format!("{:?}", value)
+            } else {
+                "********".to_string()
+            }
+        };
+
+        eprintln!("\nChanges to the database:");
+        for (path, new_value) in &diff.set {
+            let change = match self.store.get(path) {
+                Ok(Some(_)) => "changed",
+                _ => "added",
+            };
+            eprintln!("  {:8}{} = {}", change, path.display(), value(new_value));
+        }
+        for path in &diff.removed {
+            eprintln!("  {:8}{}", "removed", path.display());
+        }
+    }
+}
@@ -0,0 +1,279 @@
+//! Hands entries to a command through environment variables, for tools that read their secrets
+//! from the environment rather than from pass.
+//!
+//! Each variable holds the password of an entry, which is its first line.
+
+use crate::audit;
+use crate::context::Context;
+use crate::error::{Error, Result};
+use crate::fool;
+use crate::policy::{self, Operation};
+use crate::serve;
+use crate::store::{self, Store};
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// Whether `name` can be used as an environment variable name in a shell
+fn valid_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .map(|c| c == '_' || c.is_ascii_alphabetic())
+        .unwrap_or(false)
+        && chars.all(|c| c == '_' || c.is_ascii_alphanumeric())
+}
+
+/// Parses a `NAME=path` mapping from the command line
+pub fn parse_mapping(mapping: &str) -> std::result::Result<(String, PathBuf), String> {
+    match mapping.split_once('=') {
+        Some((name, path)) if valid_name(name) && !path.is_empty() => {
+            Ok((name.to_string(), PathBuf::from(path)))
+        }
+        Some((name, _)) if !valid_name(name) => {
+            Err(format!("{:?} is not a valid variable name", name))
+        }
+        _ => Err(format!("expected NAME=path, got {:?}", mapping)),
+    }
+}
+
+/// The variable name for an entry found under a prefix, like `DB_PASSWORD` for `db/password`
+fn variable_name(path: &Path) -> String {
+    let name: String = path
+        .to_string_lossy()
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() {
+                c.to_ascii_uppercase()
+            } else {
+                '_'
+            }
+        })
+        .collect();
+
+    if name.starts_with(|c: char| c.is_ascii_digit()) {
+        format!("_{}", name)
+    } else {
+        name
+    }
+}
+
+/// The password of the entry at `path`, which must exist and be visible
+fn password(ctx: &Context, visible: &Store, path: &Path) -> Result<String> {
+    let result = ctx.policy.check(Operation::Show, path).and_then(|_| {
+        let value = visible
+            .get(path)?
+            .ok_or_else(|| Error::NotInStore(path.to_path_buf()))?;
+
+        Ok(store::parse_entry(value).0.to_string())
+    });
+
+    audit::record("env", Some(path), result)
+}
+
+/// Fails if `name` was already given to an entry other than `path`
+fn check_collision(entries: &BTreeMap<String, PathBuf>, name: &str, path: &Path) -> Result<()> {
+    match entries.get(name) {
+        Some(other) if other != path => Err(Error::Usage(format!(
+            "{} and {} would both be {}",
+            other.display(),
+            path.display(),
+            name
+        ))),
+        _ => Ok(()),
+    }
+}
+
+/// Works out every variable, from the entries under each prefix and then from each mapping.
+/// Mappings win over prefixes when they give the same name, but two entries under the prefixes
+/// (or two mappings) that would give the same name are an error. The entry of each variable is
+/// added to `read`.
+fn variables(
+    ctx: &Context,
+    mappings: &[(String, PathBuf)],
+    prefixes: &[PathBuf],
+    read: &mut Vec<PathBuf>,
+) -> Result<BTreeMap<String, String>> {
+    let visible = ctx.policy.visible(&ctx.store)?;
+    let mut variables = BTreeMap::new();
+
+    let mut found = BTreeMap::new();
+    for prefix in prefixes {
+        // Entries are named relative to the prefix, however the prefix was written
+        let prefix = Store::normalize(prefix)?;
+        let paths: Vec<PathBuf> = visible
+            .list_paths(Some(&prefix))?
+            .map(Path::to_path_buf)
+            .collect();
+        if paths.is_empty() {
+            return Err(Error::NotInStore(prefix));
+        }
+
+        for path in paths {
+            // A prefix that is an entry itself is named after its last part
+            let relative = match path.strip_prefix(&prefix) {
+                Ok(relative) if !relative.as_os_str().is_empty() => relative,
+                _ => Path::new(path.file_name().unwrap_or(path.as_os_str())),
+            };
+            let name = variable_name(relative);
+            check_collision(&found, &name, &path)?;
+            found.insert(name, path);
+        }
+    }
+
+    let mut mapped = BTreeMap::new();
+    for (name, path) in mappings {
+        let path = Store::normalize(path)?;
+        check_collision(&mapped, name, &path)?;
+        found.remove(name);
+        mapped.insert(name.clone(), path);
+    }
+
+    for (name, path) in found.into_iter().chain(mapped) {
+        variables.insert(name, password(ctx, &visible, &path)?);
+        read.push(path);
+    }
+
+    Ok(variables)
+}
+
+/// Quotes a value for a .env file, so that it is read back exactly as it is
+fn dotenv_quote(value: &str) -> String {
+    let mut quoted = String::with_capacity(value.len() + 2);
+    quoted.push('"');
+    for c in value.chars() {
+        match c {
+            '"' | '\\' | '$' | '`' => {
+                quoted.push('\\');
+                quoted.push(c);
+            }
+            '\n' => quoted.push_str("\\n"),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+
+    quoted
+}
+
+/// Runs `command` with the variables in its environment, or prints them in .env format if
+/// `dotenv` is set
+pub fn env(
+    ctx: &Context,
+    mappings: &[(String, PathBuf)],
+    prefixes: &[PathBuf],
+    dotenv: bool,
+    command: &[OsString],
+) -> Result<()> {
+    let mut read = Vec::new();
+    let variables = variables(ctx, mappings, prefixes, &mut read)?;
+    // The command replaces this process, so the reads have to be counted before it runs
+    ctx.record_reads(read.iter().map(PathBuf::as_path));
+
+    if dotenv {
+        let mut stdout = std::io::stdout();
+        for (name, value) in &variables {
+            writeln!(stdout, "{}={}", name, dotenv_quote(value))?;
+        }
+        stdout.flush()?;
+
+        return Ok(());
+    }
+
+    let (program, arguments) = command
+        .split_first()
+        .ok_or_else(|| Error::Usage("no command to run".to_string()))?;
+
+    // The command gets the entries it was given, not the whole store, the keys to it, or a way
+    // to reach it through a server
+    let mut cmd = std::process::Command::new(program);
+    cmd.args(arguments)
+        .env_remove("IMPOSTER_PASS_STORE")
+        .env_remove(serve::SOCKET_VAR)
+        .env_remove(policy::POLICY_VAR);
+    for var in fool::REAL_STORE_VARS {
+        cmd.env_remove(var);
+    }
+    cmd.envs(&variables);
+
+    // Replace this process, so that signals and the exit code go straight to the command
+    #[cfg(target_family = "unix")]
+    {
+        use std::os::unix::process::CommandExt;
+        let err = cmd.exec();
+        Err(Error::Usage(format!("cannot run {:?}: {}", program, err)))
+    }
+
+    #[cfg(not(target_family = "unix"))]
+    {
+        let status = cmd.status()?;
+        if !status.success() {
+            return Err(Error::ProgramFailed(program.clone(), status));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn context(store: &str) -> Context {
+        Context {
+            quiet: true,
+            show_secrets: false,
+            store: store.parse().unwrap(),
+            layers: Vec::new(),
+            socket: None,
+            policy: Default::default(),
+            policy_path: None,
+            writer: None,
+            track_reads: false,
+        }
+    }
+
+    fn names(ctx: &Context, mappings: &[(&str, &str)], prefixes: &[&str]) -> Result<Vec<String>> {
+        let mappings: Vec<(String, PathBuf)> = mappings
+            .iter()
+            .map(|(name, path)| (name.to_string(), PathBuf::from(path)))
+            .collect();
+        let prefixes: Vec<PathBuf> = prefixes.iter().map(PathBuf::from).collect();
+
+        Ok(variables(ctx, &mappings, &prefixes, &mut Vec::new())?
+            .into_iter()
+            .map(|(name, value)| format!("{}={}", name, value))
+            .collect())
+    }
+
+    #[test]
+    fn prefixes_are_normalized() {
+        let ctx = context(r#"{"app/db/user": "me", "app/token": "t"}"#);
+
+        for prefix in &["app", "./app", "app/", "/app"] {
+            assert_eq!(
+                names(&ctx, &[], &[prefix]).unwrap(),
+                ["DB_USER=me", "TOKEN=t"],
+                "{}",
+                prefix
+            );
+        }
+    }
+
+    #[test]
+    fn names_that_collide_are_an_error() {
+        let ctx = context(r#"{"app/db-user": "a", "app/db/user": "b", "app/x": "x"}"#);
+        assert!(names(&ctx, &[], &["app"]).is_err());
+        assert!(names(&ctx, &[("X", "app/x"), ("X", "app/db-user")], &[]).is_err());
+
+        // Naming a variable yourself replaces the one the prefix would give
+        assert_eq!(
+            names(&ctx, &[("X", "app/db-user")], &["app/x"]).unwrap(),
+            ["X=a"]
+        );
+        assert_eq!(
+            names(&ctx, &[("X", "app/x"), ("X", "./app/x")], &[]).unwrap(),
+            ["X=x"]
+        );
+    }
+}
@@ -6,6 +6,9 @@ pub enum Error {
     #[error("(de)serialization error")]
     Serde(#[from] serde_json::Error),
 
+    #[error("d-bus error: {0}")]
+    DBus(#[from] zbus::Error),
+
     #[error("invalid insertion path: {0}")]
     InvalidPath(std::path::PathBuf),
 
@@ -14,6 +17,47 @@ pub enum Error {
 
     #[error("rm: cannot remove {0}: Is a directory")]
     IsADirectory(std::path::PathBuf),
+
+    #[error("{0}")]
+    Usage(String),
+
+    #[error("server error: {0}")]
+    Server(String),
+
+    #[error("{0:?} exited with {1}")]
+    ProgramFailed(std::ffi::OsString, std::process::ExitStatus),
+
+    #[error("{0} unexpected call(s) to pass during replay")]
+    UnexpectedCalls(usize),
+
+    #[error("error reading key: No {0} key")]
+    NoKey(&'static str),
+
+    #[error(
+        "timed out after {1:?} waiting for the lock on {0:?}, which another process is holding"
+    )]
+    LockTimeout(std::path::PathBuf, std::time::Duration),
+
+    #[error("integrity check failed for {0:?}: {1}")]
+    Integrity(std::path::PathBuf, &'static str),
+
+    #[error("refusing to use an insecure store (use --security warn to allow it):\n{0}")]
+    Insecure(String),
+
+    #[error("cannot open the sealed store: {0}")]
+    Sealed(&'static str),
+
+    #[error(
+        "the store was saved in version {0} of the format, which this imposter-pass doesn't know"
+    )]
+    UnknownVersion(u32),
+
+    #[error("{0}: cannot access {1}: Permission denied")]
+    AccessDenied(crate::policy::Operation, std::path::PathBuf),
+
+    /// Like pass, answering no when asked to confirm a change is a failure
+    #[error("{0} was left as it was")]
+    Declined(std::path::PathBuf),
 }
 
 pub type Result<T, E = Error> = std::result::Result<T, E>;
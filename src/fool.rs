@@ -0,0 +1,334 @@
+use crate::audit;
+use crate::cli::FoolOpts;
+use crate::context::{self, Context};
+use crate::error::{Error, Result};
+use crate::gpg;
+use crate::integrity;
+use crate::personality::Personality;
+use crate::policy;
+use crate::seal;
+use crate::secret_service;
+use crate::serve;
+use crate::session;
+use crate::source;
+use crate::tree;
+use std::path::*;
+
+/// Settings for the real store, which the shims mustn't apply to the temporary one, and which
+/// the programs that are run mustn't see
+pub const REAL_STORE_VARS: [&str; 6] = [
+    source::BACKUPS_VAR,
+    integrity::KEY_VAR,
+    integrity::KEY_FILE_VAR,
+    seal::PASSPHRASE_VAR,
+    seal::PASSPHRASE_FILE_VAR,
+    "IMPOSTER_PASS_SECURITY",
+];
+
+/// Runs a program with imposter-pass standing in for pass (and any other shims that were asked
+/// for)
+pub fn fool(ctx: &Context, opts: &FoolOpts) -> Result<()> {
+    let FoolOpts {
+        program,
+        arguments,
+        shims,
+        report,
+        read_only,
+        record,
+        replay,
+        secret_service,
+        tree,
+        serve,
+    } = opts;
+
+    let exec = std::env::current_exe()?;
+    let exec_dir = exec.parent().map(Path::to_path_buf).unwrap_or_default();
+
+    let tmp_dir = std::env::temp_dir().join("imposter-pass").join(
+        {
+            use std::collections::hash_map::DefaultHasher;
+            use std::hash::*;
+            use std::io::prelude::*;
+
+            let mut data = Vec::new();
+            std::fs::File::open(&exec)?.read_to_end(&mut data)?;
+
+            let mut hasher = DefaultHasher::new();
+            data.hash(&mut hasher);
+
+            hasher.finish()
+        }
+        .to_string(),
+    );
+
+    std::fs::create_dir_all(&tmp_dir)?;
+
+    // We will put the current configuration in this file.
+    // We use the hash so that we don't have to create a new file everytime,
+    // But we won't overwrite anything if multiple instances are running at once
+    let store_tmp_path = tmp_dir.join(
+        {
+            use std::collections::hash_map::DefaultHasher;
+            use std::hash::*;
+
+            let mut hasher = DefaultHasher::new();
+            std::time::Instant::now().hash(&mut hasher);
+
+            hasher.finish()
+        }
+        .to_string(),
+    );
+
+    if !ctx.quiet && *serve {
+        eprintln!(
+            "Calling {program:?} with wrapped {shims:?} in {exec_dir:?} and the store served on {socket:?}.
+Note: if you enter Ctrl-C before this program exits, or something else goes wrong,
+the changes it made to the store are lost",
+            program = program,
+            shims = shims.iter().map(|shim| shim.name()).collect::<Vec<_>>(),
+            exec_dir = tmp_dir.display(),
+            socket = store_tmp_path.with_extension("sock").display()
+        );
+    } else if !ctx.quiet {
+        eprintln!(
+            "Calling {program:?} with wrapped {shims:?} in {exec_dir:?} and temporary store={store:?}.
+Note: if you enter Ctrl-C before this program exits, or something else goes wrong,
+look in the temporary password store at {store:?}. It will contain all the changes to the store
+up until the execution was interupted",
+            program = program,
+            shims = shims.iter().map(|shim| shim.name()).collect::<Vec<_>>(),
+            exec_dir = tmp_dir.display(),
+            store = store_tmp_path.display()
+        );
+    }
+
+    // Link each shim to this executable. When it is invoked under that name, it will behave
+    // like the program it is named after, and pick up the temporary store from the environment.
+    #[cfg(target_family = "unix")]
+    for shim in shims {
+        let wrapped_exec = tmp_dir.join(shim.name());
+
+        let points_to_exec = std::fs::read_link(&wrapped_exec)
+            .map(|target| target == exec)
+            .unwrap_or(false);
+
+        if !points_to_exec {
+            // Other instances might be using the same link, so we swap it into place
+            // atomically rather than removing it first
+            let wrapped_exec_tmp = store_tmp_path.with_extension(shim.name());
+            std::os::unix::fs::symlink(&exec, &wrapped_exec_tmp)?;
+            std::fs::rename(&wrapped_exec_tmp, &wrapped_exec)?;
+        }
+    }
+
+    #[cfg(target_family = "windows")]
+    {
+        todo!(
+            "the fool command is not supported on windows at this time.
+                You can still use this application by manually setting environment variables."
+        )
+    }
+
+    // Shims other than pass don't know how to record or replay
+    if (record.is_some() || replay.is_some()) && shims.iter().any(|&shim| shim != Personality::Pass)
+    {
+        eprintln!("Warning: only calls to pass are recorded or replayed");
+    }
+
+    let mut policy = ctx.policy.clone();
+    policy.read_only |= *read_only;
+
+    // The program only gets the entries that the policy lets it see, so that it can't read the
+    // others from the temporary store either
+    let mut store = policy.visible(&ctx.store)?;
+
+    // gpg and the tree's .gpg-id need a key to agree on
+    if *tree || shims.contains(&Personality::Gpg) {
+        gpg::ensure_key(&mut store)?;
+    }
+
+    // When the store is served, it never has to be written to the disk
+    if !*serve {
+        source::write_json(&store_tmp_path, &store)?;
+    }
+
+    let path = match std::env::var_os("PATH") {
+        Some(path) => {
+            let mut new_path = tmp_dir.into_os_string();
+            new_path.push(":");
+            new_path.push(path);
+            new_path
+        }
+        None => exec_dir.into_os_string(),
+    };
+
+    // When a report is wanted, make sure that there is an audit log to build it from. The program
+    // might change directories, and the log might not exist yet, so a relative path is made
+    // absolute rather than canonical.
+    let audit_log = match audit::Log::from_env() {
+        Some(log) => Some(audit::Log::new(std::env::current_dir()?.join(log.path()))),
+        None if *report => Some(audit::Log::new(store_tmp_path.with_extension("log"))),
+        None => None,
+    };
+    let audit_log_start = audit_log.as_ref().map(audit::Log::len).transpose()?;
+
+    // Settings for the shims, which are passed on through the environment
+    let mut shim_env: Vec<(&str, std::ffi::OsString)> = Vec::new();
+    if !*serve {
+        shim_env.push(("IMPOSTER_PASS_STORE", store_tmp_path.clone().into()));
+    }
+    if let Some(audit_log) = &audit_log {
+        shim_env.push((audit::AUDIT_LOG_VAR, audit_log.path().into()));
+    }
+    if let Some(policy_path) = &ctx.policy_path {
+        // The program might change directories, so don't pass on a relative path
+        shim_env.push((
+            policy::POLICY_VAR,
+            std::fs::canonicalize(policy_path)?.into(),
+        ));
+    }
+    if *read_only {
+        shim_env.push((policy::READ_ONLY_VAR, "true".into()));
+    }
+    if ctx.track_reads {
+        shim_env.push((context::TRACK_READS_VAR, "true".into()));
+    }
+
+    // The server has to be listening before anything tries to connect to it
+    let socket = store_tmp_path.with_extension("sock");
+    let server = if *serve {
+        let server_ctx = Context {
+            quiet: true,
+            show_secrets: false,
+            store: store.clone(),
+            layers: Vec::new(),
+            socket: None,
+            policy: policy.clone(),
+            policy_path: None,
+            writer: None,
+            track_reads: false,
+        };
+        let server = serve::Server::bind(&socket, server_ctx)?;
+        shim_env.push((serve::SOCKET_VAR, socket.clone().into()));
+
+        // Only we can stop the server, since the program never sees the token
+        let token = server.shutdown_token().to_string();
+        Some((std::thread::spawn(move || server.run()), token))
+    } else {
+        None
+    };
+
+    let mut cmd = std::process::Command::new(program);
+    cmd.args(arguments)
+        .envs(std::env::vars_os())
+        .env("PATH", path)
+        .envs(shim_env.iter().cloned());
+    for var in REAL_STORE_VARS {
+        cmd.env_remove(var);
+    }
+
+    let journal_path = store_tmp_path.with_extension("journal");
+    let replay_state_path = store_tmp_path.with_extension("replay");
+    if record.is_some() {
+        cmd.env(session::RECORD_VAR, &journal_path);
+    }
+    if let Some(replay) = replay {
+        cmd.env(session::REPLAY_VAR, std::fs::canonicalize(replay)?)
+            .env(session::REPLAY_STATE_VAR, &replay_state_path);
+    }
+
+    let tree = if *tree {
+        let tree = tree::Tree::materialize(
+            store_tmp_path.with_extension("password-store"),
+            &store,
+            store.metadata(gpg::GPG_ID).unwrap_or_default(),
+        )?;
+        cmd.env(tree::PASSWORD_STORE_DIR_VAR, tree.root());
+
+        Some(tree)
+    } else {
+        None
+    };
+
+    let private_bus = if *secret_service {
+        let mut service = std::process::Command::new(&exec);
+        service
+            .arg("secret-service")
+            .envs(shim_env.iter().cloned())
+            .env("IMPOSTER_PASS_QUIET", "true");
+        for var in REAL_STORE_VARS {
+            service.env_remove(var);
+        }
+
+        let bus = secret_service::PrivateBus::start(
+            &store_tmp_path.with_extension("dbus.conf"),
+            service,
+        )?;
+        cmd.env("DBUS_SESSION_BUS_ADDRESS", &bus.address);
+
+        Some(bus)
+    } else {
+        None
+    };
+
+    let status = cmd.spawn()?.wait()?;
+    drop(private_bus);
+
+    let mut new_store = match server {
+        Some((server, token)) => {
+            serve::shutdown(&socket, &token)?;
+            server.join().expect("the store server panicked")?
+        }
+        None => serde_json::from_reader(std::fs::File::open(&store_tmp_path)?)?,
+    };
+    // Changes made to the files go on top of any made through the shims
+    if let Some(tree) = tree {
+        tree.sync(&mut new_store, &policy, audit_log.as_ref())?;
+        tree.remove()?;
+    }
+
+    if *report {
+        if let (Some(audit_log), Some(start)) = (&audit_log, audit_log_start) {
+            audit::report(program, &audit_log.read_from(start)?);
+        }
+    }
+
+    if let Some(record) = record {
+        session::Session::from_journal(&journal_path)?.save(record)?;
+    }
+
+    // The program can change its own environment, or write to the temporary store directly, so
+    // the policy is enforced again on everything it did before any of it is saved
+    policy.check_diff(&store.diff(&new_store))?;
+
+    // The entries that the program couldn't see were never given to it, so they stay as they are
+    for (path, entry) in ctx.store.entries() {
+        if !policy.shows(path) {
+            new_store.insert(path.to_path_buf(), entry.clone());
+        }
+    }
+
+    // Whichever shim made the changes, they were made by the fooled program
+    let mut diff = ctx.store.diff(&new_store);
+    diff.writer = Some(
+        Path::new(program)
+            .file_name()
+            .unwrap_or(program)
+            .to_string_lossy()
+            .into_owned(),
+    );
+    ctx.apply(diff)?;
+
+    if let Some(replay) = replay {
+        let unexpected = session::report_replay(replay, &replay_state_path)?;
+        if unexpected > 0 {
+            return Err(Error::UnexpectedCalls(unexpected));
+        }
+    }
+
+    if !status.success() {
+        return Err(Error::ProgramFailed(program.clone(), status));
+    }
+
+    Ok(())
+}
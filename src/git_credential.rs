@@ -0,0 +1,231 @@
+//! Answers git's credential helper protocol from the store.
+//!
+//! Credentials are kept at `git/<host>/<username>`, or `git/<host>/<path>/<username>` when git
+//! sends a path (with `credential.useHttpPath`). Just like other pass entries, the first line of
+//! the value is the password, and any later `key: value` lines are extra fields. The fields git
+//! understands (`username`, `password_expiry_utc` and `oauth_refresh_token`) are passed on to it,
+//! and the rest are ignored.
+//!
+//! Hosts, usernames and the parts of paths must each name a single entry, so a credential that
+//! would reach outside of `git/<host>/` is refused.
+//!
+//! To use it, configure git with
+//! `git config credential.helper '!imposter-pass --store store.json git-credential'`.
+
+use crate::audit;
+use crate::commands;
+use crate::context::Context;
+use crate::error::{Error, Result};
+use crate::policy::Operation;
+use crate::store;
+use std::collections::BTreeMap;
+use std::io::{BufRead, Write};
+use std::path::{Path, PathBuf};
+
+/// The folder that git credentials are stored in
+pub const ROOT: &str = "git";
+
+/// The attributes git sends, or is sent back
+type Credential = BTreeMap<String, String>;
+
+/// The attributes that are sent back to git as they were sent
+const ECHOED: [&str; 3] = ["protocol", "host", "path"];
+
+/// The fields of an entry that are sent to git, along with the password
+const FIELDS: [&str; 3] = ["username", "password_expiry_utc", "oauth_refresh_token"];
+
+/// Reads the attributes git sends on stdin, which end at a blank line
+fn read_credential() -> Result<Credential> {
+    let mut credential = Credential::new();
+
+    for line in std::io::stdin().lock().lines() {
+        let line = line?;
+        if line.is_empty() {
+            break;
+        }
+
+        if let Some((key, value)) = line.split_once('=') {
+            credential.insert(key.to_string(), value.to_string());
+        }
+    }
+
+    Ok(credential)
+}
+
+/// Checks that a host, username or part of a path git sent names exactly one entry component.
+///
+/// Anything else could step out of `git/<host>/`, or name a metadata entry.
+fn component(text: &str) -> Result<&str> {
+    if text.is_empty() || text.starts_with('.') || text.contains('/') {
+        return Err(Error::InvalidPath(text.into()));
+    }
+
+    Ok(text)
+}
+
+/// The folder that holds the credentials for the host (and path) git asked about
+fn folder(credential: &Credential) -> Result<Option<PathBuf>> {
+    let host = match credential.get("host") {
+        Some(host) => host,
+        None => return Ok(None),
+    };
+
+    let mut folder = Path::new(ROOT).join(component(host)?);
+    let path = credential.get("path").map(|path| path.trim_matches('/'));
+    if let Some(path) = path.filter(|path| !path.is_empty()) {
+        for part in path.split('/') {
+            folder.push(component(part)?);
+        }
+    }
+
+    Ok(Some(folder))
+}
+
+/// The entry that holds the credential for `username`
+fn entry(folder: &Path, username: &str) -> Result<PathBuf> {
+    Ok(folder.join(component(username)?))
+}
+
+/// Records a request that failed before it got to any entry, such as one with a name that is
+/// rejected, as everything else is recorded along with the entry it is about
+fn unmatched<T>(action: &str, result: Result<T>) -> Result<T> {
+    match result {
+        Ok(value) => Ok(value),
+        Err(err) => audit::record(action, None, Err(err)),
+    }
+}
+
+/// The entries that hold credentials matching what git asked for
+fn matching(ctx: &Context, credential: &Credential) -> Result<Vec<PathBuf>> {
+    let folder = match folder(credential)? {
+        Some(folder) => folder,
+        None => return Ok(Vec::new()),
+    };
+
+    let visible = ctx.policy.visible(&ctx.store)?;
+    Ok(match credential.get("username") {
+        Some(username) => {
+            let path = entry(&folder, username)?;
+            visible.get(&path)?.map(|_| vec![path]).unwrap_or_default()
+        }
+        // Without a username, any credential directly in the folder will do
+        None => visible
+            .list_paths(Some(&folder))?
+            .filter(|path| path.parent() == Some(folder.as_path()))
+            .map(Path::to_path_buf)
+            .collect(),
+    })
+}
+
+fn get(ctx: &Context, credential: &Credential) -> Result<()> {
+    let paths = unmatched("git-credential get", matching(ctx, credential))?;
+    let path = match paths.into_iter().next() {
+        Some(path) => path,
+        // Saying nothing lets git try the next helper, or ask the user
+        None => return Ok(()),
+    };
+
+    let result = ctx.policy.check(Operation::Show, &path).and_then(|_| {
+        let value = ctx.store.get(&path)?.unwrap_or_default();
+        let (password, fields) = store::parse_entry(value);
+
+        let mut reply: Credential = credential
+            .iter()
+            .filter(|(key, _)| ECHOED.contains(&key.as_str()))
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect();
+        for (key, value) in fields {
+            // Other pass tools call the username login or user
+            let key = match key {
+                "login" | "user" => "username",
+                key => key,
+            };
+            if FIELDS.contains(&key) {
+                reply.insert(key.to_string(), value.to_string());
+            }
+        }
+        if let Some(username) = path.file_name() {
+            reply
+                .entry("username".to_string())
+                .or_insert_with(|| username.to_string_lossy().into_owned());
+        }
+        reply.insert("password".to_string(), password.to_string());
+
+        let mut stdout = std::io::stdout();
+        for (key, value) in reply {
+            writeln!(stdout, "{}={}", key, value)?;
+        }
+        stdout.flush()?;
+
+        ctx.record_reads([path.as_path()]);
+        Ok(())
+    });
+
+    audit::record("git-credential get", Some(&path), result)
+}
+
+fn store(ctx: &Context, credential: &Credential) -> Result<()> {
+    let folder = unmatched("git-credential store", folder(credential))?;
+    let (path, username, password) = match (
+        folder,
+        credential.get("username"),
+        credential.get("password"),
+    ) {
+        (Some(folder), Some(username), Some(password)) => (
+            unmatched("git-credential store", entry(&folder, username))?,
+            username,
+            password,
+        ),
+        _ => return Ok(()),
+    };
+
+    let result = ctx.policy.check(Operation::Insert, &path).and_then(|_| {
+        let mut store = ctx.store.clone();
+
+        // Keep the fields of an existing entry, and only change its password
+        if store.get(&path)?.is_some() {
+            commands::set_password(&mut store, &path, password.as_str().into(), true)?;
+        } else {
+            let mut value = format!("{}\nusername: {}\n", password, username);
+            if let Some(protocol) = credential.get("protocol") {
+                value.push_str(&format!("protocol: {}\n", protocol));
+            }
+            *store.value_mut(&path)? = value.into();
+        }
+
+        ctx.save(&store)
+    });
+
+    audit::record("git-credential store", Some(&path), result)
+}
+
+fn erase(ctx: &Context, credential: &Credential) -> Result<()> {
+    let paths = unmatched("git-credential erase", matching(ctx, credential))?;
+    if paths.is_empty() {
+        return Ok(());
+    }
+
+    let mut store = ctx.store.clone();
+    for path in &paths {
+        let result = ctx
+            .policy
+            .check(Operation::Rm, path)
+            .and_then(|_| store.remove(path));
+        audit::record("git-credential erase", Some(path), result)?;
+    }
+
+    ctx.save(&store)
+}
+
+/// Runs a git credential helper action: `get`, `store` or `erase`
+pub fn run(ctx: &Context, action: &str) -> Result<()> {
+    let credential = read_credential()?;
+
+    match action {
+        "get" => get(ctx, &credential),
+        "store" => store(ctx, &credential),
+        "erase" => erase(ctx, &credential),
+        // Helpers are supposed to ignore actions they don't know about
+        _ => Ok(()),
+    }
+}
@@ -0,0 +1,149 @@
+//! Translates the gopass command line onto the store
+
+use crate::cli::StoreOpts;
+use crate::commands;
+use crate::context::Context;
+use crate::error::{Error, Result};
+use crate::secret::Secret;
+use std::io::IsTerminal;
+use std::path::{Path, PathBuf};
+use structopt::StructOpt;
+
+/// The subset of gopass that programs actually call
+#[derive(Debug, StructOpt)]
+pub enum GopassCmd {
+    /// Lists the entries in the store
+    ///
+    /// This tries to have the same behaviour as gopass ls
+    #[structopt(name = "ls", alias = "list")]
+    List {
+        #[structopt(name = "secret", parse(from_os_str))]
+        name: Option<PathBuf>,
+    },
+
+    /// Shows the value of an entry
+    ///
+    /// This tries to have the same behaviour as gopass show <SECRET>
+    Show {
+        #[structopt(name = "secret", parse(from_os_str))]
+        name: PathBuf,
+
+        /// Only show the password, which is the first line of the entry
+        #[structopt(name = "password", long, short = "o")]
+        password: bool,
+    },
+
+    /// Inserts a new entry
+    ///
+    /// This tries to have the same behaviour as gopass insert <SECRET>. If stdin is not a
+    /// terminal, the entry is read from stdin without any prompts.
+    Insert {
+        #[structopt(name = "secret", parse(from_os_str))]
+        name: PathBuf,
+
+        #[structopt(name = "echo", long, short)]
+        echo: bool,
+
+        #[structopt(name = "multiline", long, short, conflicts_with = "echo")]
+        multiline: bool,
+
+        #[structopt(name = "force", long, short)]
+        force: bool,
+    },
+}
+
+#[derive(Debug, StructOpt)]
+#[structopt(name = "gopass")]
+pub struct Gopass {
+    #[structopt(flatten)]
+    pub opts: StoreOpts,
+
+    #[structopt(subcommand)]
+    pub cmd: GopassCmd,
+}
+
+impl GopassCmd {
+    /// The name of the subcommand, for the audit log
+    pub fn name(&self) -> &'static str {
+        match self {
+            GopassCmd::List { .. } => "gopass ls",
+            GopassCmd::Show { .. } => "gopass show",
+            GopassCmd::Insert { .. } => "gopass insert",
+        }
+    }
+
+    pub fn pass_name(&self) -> Option<&Path> {
+        match self {
+            GopassCmd::List { name } => name.as_deref(),
+            GopassCmd::Show { name, .. } | GopassCmd::Insert { name, .. } => Some(name),
+        }
+    }
+}
+
+impl Gopass {
+    /// Parses the command line the way gopass would.
+    ///
+    /// Just like gopass, running without a subcommand lists the store, and giving an entry name
+    /// in place of a subcommand shows that entry.
+    pub fn from_gopass_args<I>(args: I) -> Self
+    where
+        I: IntoIterator,
+        I::Item: Into<std::ffi::OsString>,
+    {
+        let mut args: Vec<std::ffi::OsString> = args.into_iter().map(Into::into).collect();
+
+        let subcommand = args.get(1).map(|arg| arg.to_string_lossy().into_owned());
+        match subcommand.as_deref() {
+            None => args.push("ls".into()),
+            Some(arg) if arg.starts_with('-') => {}
+            Some("ls") | Some("list") | Some("show") | Some("insert") | Some("help") => {}
+            Some(_) => args.insert(1, "show".into()),
+        }
+
+        let mut gopass = Self::from_iter(args);
+        gopass.opts.quiet = true;
+        gopass
+    }
+}
+
+pub fn run(ctx: &Context, cmd: &GopassCmd) -> Result<()> {
+    match cmd {
+        GopassCmd::List { name } => commands::show(ctx, name.as_deref()),
+        GopassCmd::Show { name, password } => {
+            let visible = ctx.policy.visible(&ctx.store)?;
+
+            match visible.get(name)? {
+                Some(value) if *password => {
+                    println!("{}", value.lines().next().unwrap_or_default());
+                    ctx.record_reads([name.as_path()]);
+                    Ok(())
+                }
+                Some(value) => {
+                    print!("{}", value);
+                    if !value.ends_with('\n') {
+                        println!();
+                    }
+                    ctx.record_reads([name.as_path()]);
+                    Ok(())
+                }
+                // This might be a folder, which gopass lists
+                None if visible.list(Some(name))?.next().is_some() => visible.show(Some(name)),
+                None => Err(Error::NotInStore(name.clone())),
+            }
+        }
+        GopassCmd::Insert {
+            name,
+            echo,
+            multiline,
+            force,
+        } => {
+            if std::io::stdin().is_terminal() {
+                commands::insert(ctx, name, *echo, *multiline, *force)
+            } else {
+                let value = Secret::read_to_end(&mut std::io::stdin())?;
+
+                commands::set(ctx, name, value)
+            }
+        }
+    }
+}
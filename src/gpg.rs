@@ -0,0 +1,468 @@
+//! Just enough of gpg for programs that set up a key before they use pass.
+//!
+//! None of the keys are real. Each one is a pair of random fingerprints and a user id, kept in
+//! the store's metadata, and the fingerprint of every key is listed in `.gpg-id` just like
+//! `pass init` would do. "Encrypting" leaves the data as it is, which matches the unencrypted
+//! files that `fool --tree` writes.
+
+use crate::context::Context;
+use crate::error::{Error, Result};
+use crate::policy::Operation;
+use crate::store::Store;
+use crate::tree;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::ffi::OsString;
+use std::io::{IsTerminal, Read, Write};
+use std::path::{Path, PathBuf};
+
+/// The metadata that holds the key ids to encrypt with, one per line, as in a real password store
+pub const GPG_ID: &str = ".gpg-id";
+
+/// The metadata that holds every key gpg knows about
+const KEYS: &str = ".imposter/gpg-keys";
+
+/// The user id of the key that is made when a program needs one but never made its own
+const DEFAULT_UID: &str = "imposter-pass <imposter-pass@localhost>";
+
+const ARMOR_BEGIN: &str = "-----BEGIN PGP MESSAGE-----\n\n";
+const ARMOR_END: &str = "-----END PGP MESSAGE-----\n";
+
+/// The options that take a value, which has to be skipped over when looking for files
+const OPTIONS_WITH_VALUES: &[&str] = &[
+    "-r",
+    "--recipient",
+    "-u",
+    "--local-user",
+    "-o",
+    "--output",
+    "-z",
+    "--homedir",
+    "--default-key",
+    "--passphrase",
+    "--passphrase-fd",
+    "--passphrase-file",
+    "--pinentry-mode",
+    "--compress-algo",
+    "--cipher-algo",
+    "--digest-algo",
+    "--trust-model",
+    "--status-fd",
+    "--keyring",
+];
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Key {
+    pub fingerprint: String,
+    pub subkey_fingerprint: String,
+    pub uid: String,
+    pub created: DateTime<Utc>,
+}
+
+impl Key {
+    fn generate(uid: &str) -> Self {
+        Self {
+            fingerprint: random_fingerprint(),
+            subkey_fingerprint: random_fingerprint(),
+            uid: uid.to_string(),
+            created: Utc::now(),
+        }
+    }
+
+    /// The long key id, which is the end of the fingerprint
+    fn key_id(fingerprint: &str) -> &str {
+        &fingerprint[fingerprint.len() - 16..]
+    }
+
+    /// Whether the key is the one that a user named with `pattern`, which can be part of the
+    /// user id or the end of the fingerprint
+    fn matches(&self, pattern: &str) -> bool {
+        let pattern = pattern.trim_start_matches("0x");
+
+        self.fingerprint.ends_with(&pattern.to_uppercase())
+            || self.uid.to_lowercase().contains(&pattern.to_lowercase())
+    }
+}
+
+fn random_fingerprint() -> String {
+    use rand::RngCore;
+
+    let mut bytes = [0u8; 20];
+    rand::rngs::OsRng.fill_bytes(&mut bytes);
+
+    bytes.iter().map(|byte| format!("{:02X}", byte)).collect()
+}
+
+/// Every key in `store`
+pub fn keys(store: &Store) -> Result<Vec<Key>> {
+    Ok(match store.metadata(KEYS) {
+        Some(keys) => serde_json::from_str(keys)?,
+        None => Vec::new(),
+    })
+}
+
+/// Adds `key` to the store, and to `.gpg-id`
+fn add_key(store: &mut Store, key: Key) -> Result<()> {
+    let mut gpg_id = store.metadata(GPG_ID).unwrap_or_default().to_string();
+    gpg_id.push_str(&key.fingerprint);
+    gpg_id.push('\n');
+    store.set_metadata(GPG_ID, gpg_id);
+
+    let mut keys = keys(store)?;
+    keys.push(key);
+    store.set_metadata(KEYS, serde_json::to_string(&keys)?);
+
+    Ok(())
+}
+
+/// Makes sure that the store has at least one key, so that gpg and `.gpg-id` have something to
+/// agree on
+pub fn ensure_key(store: &mut Store) -> Result<()> {
+    if keys(store)?.is_empty() {
+        add_key(store, Key::generate(DEFAULT_UID))?;
+    }
+
+    Ok(())
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum GpgCmd {
+    Version,
+    ListKeys { secret: bool },
+    GenKey { quick: bool },
+    Encrypt,
+    Decrypt,
+}
+
+/// A gpg command line. gpg's options don't fit structopt well, since the command is itself an
+/// option, so this is parsed by hand. Options that don't matter here are ignored.
+#[derive(Debug)]
+pub struct Gpg {
+    pub cmd: GpgCmd,
+    batch: bool,
+    armor: bool,
+    with_colons: bool,
+    recipients: Vec<String>,
+    output: Option<PathBuf>,
+    arguments: Vec<OsString>,
+}
+
+impl Gpg {
+    pub fn from_gpg_args<I>(args: I) -> Result<Self>
+    where
+        I: IntoIterator,
+        I::Item: Into<OsString>,
+    {
+        let mut cmd = None;
+        let mut batch = false;
+        let mut armor = false;
+        let mut with_colons = false;
+        let mut recipients = Vec::new();
+        let mut output = None;
+        let mut arguments = Vec::new();
+
+        let mut args = args.into_iter().map(Into::into).skip(1);
+        while let Some(arg) = args.next() {
+            let arg_str = match arg.to_str() {
+                Some(arg_str) if arg_str.starts_with('-') && arg_str != "-" => arg_str,
+                _ => {
+                    arguments.push(arg);
+                    continue;
+                }
+            };
+
+            // Options can be given as --option=value as well as --option value
+            let (option, value) = match arg_str.split_once('=') {
+                Some((option, value)) if option.starts_with("--") => {
+                    (option, Some(OsString::from(value)))
+                }
+                _ => (arg_str, None),
+            };
+            let value = if OPTIONS_WITH_VALUES.contains(&option) {
+                value.or_else(|| args.next())
+            } else {
+                None
+            };
+
+            let set_cmd = |cmd: &mut Option<GpgCmd>, new_cmd| match cmd {
+                Some(_) => Err(Error::Usage("gpg: conflicting commands".to_string())),
+                None => {
+                    *cmd = Some(new_cmd);
+                    Ok(())
+                }
+            };
+
+            match option {
+                "--version" => set_cmd(&mut cmd, GpgCmd::Version)?,
+                "-k" | "--list-keys" | "--list-public-keys" => {
+                    set_cmd(&mut cmd, GpgCmd::ListKeys { secret: false })?
+                }
+                "-K" | "--list-secret-keys" => {
+                    set_cmd(&mut cmd, GpgCmd::ListKeys { secret: true })?
+                }
+                "--gen-key" | "--generate-key" | "--full-gen-key" | "--full-generate-key" => {
+                    set_cmd(&mut cmd, GpgCmd::GenKey { quick: false })?
+                }
+                "--quick-gen-key" | "--quick-generate-key" => {
+                    set_cmd(&mut cmd, GpgCmd::GenKey { quick: true })?
+                }
+                "-e" | "--encrypt" => set_cmd(&mut cmd, GpgCmd::Encrypt)?,
+                "-d" | "--decrypt" => set_cmd(&mut cmd, GpgCmd::Decrypt)?,
+                "--batch" => batch = true,
+                "-a" | "--armor" | "--armour" => armor = true,
+                "--with-colons" => with_colons = true,
+                "-r" | "--recipient" => {
+                    recipients.extend(value.map(|value| value.to_string_lossy().into_owned()))
+                }
+                "-o" | "--output" => output = value.map(PathBuf::from),
+                _ => {}
+            }
+        }
+
+        Ok(Self {
+            cmd: cmd.ok_or_else(|| Error::Usage("gpg: no command given".to_string()))?,
+            batch,
+            armor,
+            with_colons,
+            recipients,
+            output,
+            arguments,
+        })
+    }
+
+    /// The name of the command, for the audit log
+    pub fn name(&self) -> &'static str {
+        match self.cmd {
+            GpgCmd::Version => "gpg version",
+            GpgCmd::ListKeys { .. } => "gpg list-keys",
+            GpgCmd::GenKey { .. } => "gpg gen-key",
+            GpgCmd::Encrypt => "gpg encrypt",
+            GpgCmd::Decrypt => "gpg decrypt",
+        }
+    }
+
+    /// The password being decrypted or encrypted, if it is a file in PASSWORD_STORE_DIR
+    pub fn pass_name(&self) -> Option<PathBuf> {
+        let store_dir =
+            std::fs::canonicalize(std::env::var_os(tree::PASSWORD_STORE_DIR_VAR)?).ok()?;
+
+        let file = match self.cmd {
+            GpgCmd::Encrypt => self.output.as_ref()?.clone(),
+            GpgCmd::Decrypt => PathBuf::from(self.arguments.first()?),
+            _ => return None,
+        };
+        let file = std::fs::canonicalize(file.parent()?)
+            .ok()?
+            .join(file.file_name()?);
+
+        tree::entry_name(file.strip_prefix(store_dir).ok()?)
+    }
+
+    /// Reads the input file, or stdin if there isn't one
+    fn input(&self) -> Result<Vec<u8>> {
+        let mut data = Vec::new();
+
+        match self.arguments.first() {
+            Some(file) if file != "-" => {
+                std::fs::File::open(file)?.read_to_end(&mut data)?;
+            }
+            _ => {
+                std::io::stdin().read_to_end(&mut data)?;
+            }
+        }
+
+        Ok(data)
+    }
+
+    /// Writes to the output file, or stdout if there isn't one
+    fn output(&self, data: &[u8]) -> Result<()> {
+        match &self.output {
+            Some(file) if file != Path::new("-") => std::fs::write(file, data)?,
+            _ => {
+                std::io::stdout().write_all(data)?;
+                std::io::stdout().flush()?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// The data inside an ASCII armored message made by [`run`], or `data` itself if it isn't one
+pub fn dearmor(data: &[u8]) -> &[u8] {
+    data.strip_prefix(ARMOR_BEGIN.as_bytes())
+        .and_then(|data| data.strip_suffix(ARMOR_END.as_bytes()))
+        .and_then(|data| data.strip_suffix(b"\n"))
+        .unwrap_or(data)
+}
+
+/// Works out the user id for a new key from a gpg parameter file
+fn parameters_uid(parameters: &str) -> Option<String> {
+    let parameter = |name: &str| {
+        parameters.lines().find_map(|line| {
+            let (key, value) = line.split_once(':')?;
+            if key.trim().eq_ignore_ascii_case(name) {
+                Some(value.trim().to_string())
+            } else {
+                None
+            }
+        })
+    };
+
+    let mut uid = parameter("Name-Real")?;
+    if let Some(comment) = parameter("Name-Comment") {
+        uid.push_str(&format!(" ({})", comment));
+    }
+    if let Some(email) = parameter("Name-Email") {
+        uid.push_str(&format!(" <{}>", email));
+    }
+
+    Some(uid)
+}
+
+fn gnupg_home() -> PathBuf {
+    std::env::var_os("GNUPGHOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".gnupg")))
+        .unwrap_or_default()
+}
+
+fn print_key(key: &Key, secret: bool, with_colons: bool) {
+    let (primary, sub) = if secret {
+        ("sec", "ssb")
+    } else {
+        ("pub", "sub")
+    };
+    let created = key.created.format("%Y-%m-%d");
+    let timestamp = key.created.timestamp();
+
+    if with_colons {
+        println!(
+            "{}:u:3072:1:{}:{}:::u:::scESC:::{}:::23::0:",
+            primary,
+            Key::key_id(&key.fingerprint),
+            timestamp,
+            if secret { "+" } else { "" }
+        );
+        println!("fpr:::::::::{}:", key.fingerprint);
+        println!("uid:u::::{}::::{}::::::::::0:", timestamp, key.uid);
+        println!(
+            "{}:u:3072:1:{}:{}::::::e:::{}:::23:",
+            sub,
+            Key::key_id(&key.subkey_fingerprint),
+            timestamp,
+            if secret { "+" } else { "" }
+        );
+        println!("fpr:::::::::{}:", key.subkey_fingerprint);
+    } else {
+        println!("{}   rsa3072 {} [SC]", primary, created);
+        println!("      {}", key.fingerprint);
+        println!("uid           [ultimate] {}", key.uid);
+        println!("{}   rsa3072 {} [E]", sub, created);
+        println!();
+    }
+}
+
+pub fn run(ctx: &Context, gpg: &Gpg) -> Result<()> {
+    let keys = keys(&ctx.store)?;
+
+    match gpg.cmd {
+        GpgCmd::Version => {
+            // In one write, since this is often piped into head
+            let version = format!(
+                "gpg (GnuPG) 2.2.40\nlibgcrypt 1.10.1\nHome: {}\n",
+                gnupg_home().display()
+            );
+            std::io::stdout().write_all(version.as_bytes())?;
+        }
+        GpgCmd::ListKeys { secret } => {
+            let listed = keys
+                .iter()
+                .filter(|key| {
+                    gpg.arguments.is_empty()
+                        || gpg
+                            .arguments
+                            .iter()
+                            .any(|pattern| key.matches(&pattern.to_string_lossy()))
+                })
+                .collect::<Vec<_>>();
+
+            if listed.is_empty() && !gpg.arguments.is_empty() {
+                return Err(Error::NoKey(if secret { "secret" } else { "public" }));
+            }
+
+            if !gpg.with_colons && !listed.is_empty() {
+                let keyring = gnupg_home().join("pubring.kbx");
+                println!("{}", keyring.display());
+                println!("{}", "-".repeat(keyring.to_string_lossy().len()));
+            }
+            for key in listed {
+                print_key(key, secret, gpg.with_colons);
+            }
+        }
+        GpgCmd::GenKey { quick } => {
+            ctx.policy.check(Operation::Insert, GPG_ID)?;
+
+            let uid = if quick {
+                gpg.arguments
+                    .first()
+                    .map(|uid| uid.to_string_lossy().into_owned())
+                    .ok_or_else(|| {
+                        Error::Usage("usage: gpg [options] --quick-generate-key user-id".into())
+                    })?
+            } else if !gpg.arguments.is_empty() || !std::io::stdin().is_terminal() {
+                // Keys are generated from a parameter file in batch mode
+                let parameters = String::from_utf8_lossy(&gpg.input()?).into_owned();
+                parameters_uid(&parameters).unwrap_or_else(|| DEFAULT_UID.to_string())
+            } else {
+                DEFAULT_UID.to_string()
+            };
+
+            let key = Key::generate(&uid);
+            eprintln!(
+                "gpg: key {} marked as ultimately trusted",
+                Key::key_id(&key.fingerprint)
+            );
+            if !gpg.batch {
+                println!("public and secret key created and signed.\n");
+                print_key(&key, false, false);
+            }
+
+            let mut store = ctx.store.clone();
+            add_key(&mut store, key)?;
+            ctx.save(&store)?;
+
+            // If fool --tree made a password store, keep its .gpg-id in step with the key list
+            if let Some(store_dir) = std::env::var_os(tree::PASSWORD_STORE_DIR_VAR) {
+                let gpg_id = Path::new(&store_dir).join(GPG_ID);
+                if gpg_id.exists() {
+                    std::fs::write(gpg_id, store.metadata(GPG_ID).unwrap_or_default())?;
+                }
+            }
+        }
+        GpgCmd::Encrypt => {
+            for recipient in &gpg.recipients {
+                if !keys.iter().any(|key| key.matches(recipient)) {
+                    return Err(Error::NoKey("public"));
+                }
+            }
+
+            let data = gpg.input()?;
+            if gpg.armor {
+                let mut armored = ARMOR_BEGIN.as_bytes().to_vec();
+                armored.extend_from_slice(&data);
+                armored.push(b'\n');
+                armored.extend_from_slice(ARMOR_END.as_bytes());
+
+                gpg.output(&armored)?;
+            } else {
+                gpg.output(&data)?;
+            }
+        }
+        GpgCmd::Decrypt => {
+            gpg.output(dearmor(&gpg.input()?))?;
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,304 @@
+//! A small HTTP API over the store, for test harnesses that would rather not shell out to pass.
+//!
+//! Every request needs an `Authorization: Bearer <token>` header. Bodies are JSON, and so are
+//! responses, with errors given as `{"error": "..."}`.
+//!
+//! - `GET /entries?prefix=<path>` lists the entries, as `["a/b", ...]`
+//! - `GET /entries/<path>` shows an entry, as `{"path": "a/b", "value": "..."}`
+//! - `PUT /entries/<path>` inserts an entry from `{"value": "..."}`
+//! - `DELETE /entries/<path>` removes an entry, or a folder with `?recursive=true`
+//! - `POST /generate/<path>` generates a password from
+//!   `{"length": 25, "no_symbols": false, "in_place": false}`, where each field is optional,
+//!   and returns the entry like `GET` does
+
+use crate::audit;
+use crate::commands;
+use crate::context::Context;
+use crate::error::{Error, Result};
+use crate::policy::Operation;
+use crate::secret::Secret;
+use crate::store::Store;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use tiny_http::{Header, Method, Request, Response, Server};
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct InsertBody {
+    value: Secret,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct GenerateBody {
+    length: usize,
+    no_symbols: bool,
+    in_place: bool,
+}
+
+impl Default for GenerateBody {
+    fn default() -> Self {
+        Self {
+            length: 25,
+            no_symbols: false,
+            in_place: false,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct EntryBody<'a> {
+    path: &'a Path,
+    value: &'a str,
+}
+
+/// A response that hasn't been turned into HTTP yet
+type Reply = Result<(u16, serde_json::Value)>;
+
+/// Serves the store on `listen` until the process is killed.
+///
+/// Without a token, a random one is made up and printed.
+pub fn serve(ctx: &Context, listen: &str, token: Option<&str>) -> Result<()> {
+    let token = match token {
+        Some(token) => token.to_string(),
+        None => {
+            let token = commands::generate_password(32, false).to_string();
+            println!("Token: {}", token);
+            token
+        }
+    };
+
+    let server = Server::http(listen)
+        .map_err(|err| Error::Usage(format!("cannot listen on {}: {}", listen, err)))?;
+    let addr = server.server_addr().to_ip();
+
+    if let Some(addr) = addr {
+        println!("Listening on http://{}", addr);
+        if !addr.ip().is_loopback() {
+            eprintln!("Warning: {} can be reached from other machines", addr);
+        }
+    }
+
+    let mut ctx = ctx.clone();
+    for mut request in server.incoming_requests() {
+        let reply = if authorized(&request, &token) {
+            handle(&mut ctx, &mut request)
+        } else {
+            Ok((401, json!({ "error": "missing or wrong bearer token" })))
+        };
+
+        let (status, body) =
+            reply.unwrap_or_else(|err| (status(&err), json!({ "error": err.to_string() })));
+        let response = Response::from_string(body.to_string())
+            .with_status_code(status)
+            .with_header(
+                "Content-Type: application/json"
+                    .parse::<Header>()
+                    .expect("header is valid"),
+            );
+
+        // A client that goes away shouldn't take the server down with it
+        if let Err(err) = request.respond(response) {
+            if !ctx.quiet {
+                eprintln!("Warning: failed to respond to a request: {}", err);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn authorized(request: &Request, token: &str) -> bool {
+    let expected = format!("Bearer {}", token);
+
+    request.headers().iter().any(|header| {
+        header.field.equiv("Authorization") && {
+            // Compare every byte, so that the time taken doesn't give away how much matched
+            let value = header.value.as_bytes();
+            value.len() == expected.len()
+                && value
+                    .iter()
+                    .zip(expected.as_bytes())
+                    .fold(0, |diff, (a, b)| diff | (a ^ b))
+                    == 0
+        }
+    })
+}
+
+/// The HTTP status that goes with an error
+fn status(err: &Error) -> u16 {
+    match err {
+        Error::NotInStore(_) => 404,
+        Error::AccessDenied(..) => 403,
+        Error::IsADirectory(_) => 409,
+        Error::InvalidPath(_) | Error::Usage(_) | Error::Serde(_) => 400,
+        _ => 500,
+    }
+}
+
+/// Decodes the `%XX` escapes in part of a URL
+fn percent_decode(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = if bytes[i] == b'%' {
+            text.get(i + 1..i + 3)
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+        } else {
+            None
+        };
+
+        match escaped {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            None => {
+                decoded.push(if bytes[i] == b'+' { b' ' } else { bytes[i] });
+                i += 1;
+            }
+        }
+    }
+
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+fn handle(ctx: &mut Context, request: &mut Request) -> Reply {
+    let url = request.url().to_string();
+    let (path, query) = url.split_once('?').unwrap_or((&url, ""));
+    let query: BTreeMap<String, String> = query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            (percent_decode(key), percent_decode(value))
+        })
+        .collect();
+
+    let mut body = String::new();
+    request.as_reader().read_to_string(&mut body)?;
+
+    // Pick up changes made by anything else using the store
+    ctx.reload()?;
+
+    // Empty segments are kept, so that `a//b` is turned down rather than read as `a/b`
+    let segments: Vec<&str> = path.trim_end_matches('/').split('/').skip(1).collect();
+    match (request.method(), segments.split_first()) {
+        (Method::Get, Some((&"entries", []))) => {
+            let prefix = query.get("prefix").map(PathBuf::from);
+            audit::record("http ls", prefix.as_deref(), list(ctx, prefix.as_deref()))
+        }
+        (method, Some((&"entries", name))) if !name.is_empty() => {
+            let name = entry_name(name)?;
+
+            match method {
+                Method::Get => audit::record("http show", Some(&name), show(ctx, &name)),
+                Method::Put => audit::record("http insert", Some(&name), insert(ctx, &name, &body)),
+                Method::Delete => {
+                    let recursive = query.get("recursive").map(String::as_str) == Some("true");
+                    audit::record("http rm", Some(&name), remove(ctx, &name, recursive))
+                }
+                _ => Ok((405, json!({ "error": "method not allowed" }))),
+            }
+        }
+        (Method::Post, Some((&"generate", name))) if !name.is_empty() => {
+            let name = entry_name(name)?;
+            audit::record("http generate", Some(&name), generate(ctx, &name, &body))
+        }
+        _ => Ok((404, json!({ "error": "no such endpoint" }))),
+    }
+}
+
+/// The entry that the segments of a URL name.
+///
+/// Each segment has to be one component of the name, so that `.`, `..`, empty segments and
+/// escaped slashes can't be used to reach an entry under a different name than the policy sees.
+fn entry_name(segments: &[&str]) -> Result<PathBuf> {
+    let decoded: Vec<String> = segments
+        .iter()
+        .map(|segment| percent_decode(segment))
+        .collect();
+
+    if decoded.iter().any(|segment| {
+        segment.is_empty() || segment == "." || segment == ".." || segment.contains('/')
+    }) {
+        return Err(Error::InvalidPath(decoded.join("/").into()));
+    }
+
+    Store::normalize(decoded.iter().collect::<PathBuf>())
+}
+
+fn list(ctx: &Context, prefix: Option<&Path>) -> Reply {
+    let visible = ctx.policy.visible(&ctx.store)?;
+    let paths: Vec<&Path> = visible.list_paths(prefix)?.collect();
+
+    Ok((200, json!(paths)))
+}
+
+fn show(ctx: &Context, name: &Path) -> Reply {
+    ctx.policy.check(Operation::Show, name)?;
+
+    let value = ctx
+        .store
+        .get(name)?
+        .ok_or_else(|| Error::NotInStore(name.to_path_buf()))?;
+    ctx.record_reads([name]);
+
+    Ok((200, json!(EntryBody { path: name, value })))
+}
+
+fn insert(ctx: &mut Context, name: &Path, body: &str) -> Reply {
+    let body: InsertBody = serde_json::from_str(body)?;
+    ctx.policy.check(Operation::Insert, name)?;
+
+    let mut store = ctx.store.clone();
+    *store.value_mut(name)? = body.value.clone();
+    save(ctx, store)?;
+
+    Ok((
+        200,
+        json!(EntryBody {
+            path: name,
+            value: &body.value
+        }),
+    ))
+}
+
+fn remove(ctx: &mut Context, name: &Path, recursive: bool) -> Reply {
+    commands::check_remove(ctx, name, recursive)?;
+
+    let mut store = ctx.store.clone();
+    store.remove(name)?;
+    save(ctx, store)?;
+
+    Ok((200, json!({})))
+}
+
+fn generate(ctx: &mut Context, name: &Path, body: &str) -> Reply {
+    let body: GenerateBody = if body.trim().is_empty() {
+        GenerateBody::default()
+    } else {
+        serde_json::from_str(body)?
+    };
+    ctx.policy.check(Operation::Generate, name)?;
+
+    let password = commands::generate_password(body.length, !body.no_symbols);
+    let mut store = ctx.store.clone();
+    commands::set_password(&mut store, name, password, body.in_place)?;
+    save(ctx, store)?;
+
+    // The new password is returned even if the policy wouldn't allow it to be shown later
+    let value = ctx.store.get(name)?.unwrap_or_default();
+    Ok((200, json!(EntryBody { path: name, value })))
+}
+
+/// Saves `store`, and keeps it as the current store in case it only lives in memory
+fn save(ctx: &mut Context, store: Store) -> Result<()> {
+    ctx.save(&store)?;
+    ctx.store = store;
+    Ok(())
+}
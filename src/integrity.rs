@@ -0,0 +1,202 @@
+//! Detects store files that were changed by something other than imposter-pass, before a fooled
+//! program is handed bad credentials.
+//!
+//! When a key is given, store files are saved inside an envelope that holds an HMAC-SHA256 of
+//! the store, like `{"hmac-sha256": "<hex>", "store": {...}}`. Loading a file fails if its MAC
+//! doesn't match, or if it has no envelope at all, since removing the envelope would otherwise
+//! be an easy way around the check. `imposter-pass verify --sign` adds the envelope to existing
+//! store files, which is the only way a file without one is ever accepted.
+
+use crate::cli::StoreOpts;
+use crate::context::Context;
+use crate::error::{Error, Result};
+use crate::lock::Lock;
+use crate::source::{self, Source};
+use crate::store::Store;
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::path::{Path, PathBuf};
+use zeroize::Zeroizing;
+
+/// The key to protect store files with
+pub const KEY_VAR: &str = "IMPOSTER_PASS_MAC_KEY";
+
+/// A file holding the key to protect store files with, used if IMPOSTER_PASS_MAC_KEY isn't set
+pub const KEY_FILE_VAR: &str = "IMPOSTER_PASS_MAC_KEY_FILE";
+
+/// A store file with a MAC
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Envelope {
+    #[serde(rename = "hmac-sha256")]
+    mac: String,
+    store: Store,
+}
+
+/// An envelope to be saved, which borrows the store rather than copying it
+#[derive(Debug, Serialize)]
+pub struct Sealed<'a> {
+    #[serde(rename = "hmac-sha256")]
+    mac: String,
+    store: &'a Store,
+}
+
+/// What a store file can hold
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+pub enum Stored {
+    Protected(Envelope),
+    Plain(Store),
+}
+
+/// The key given with IMPOSTER_PASS_MAC_KEY or IMPOSTER_PASS_MAC_KEY_FILE, if there is one
+pub fn key() -> Result<Option<Zeroizing<Vec<u8>>>> {
+    let key = if let Some(key) = std::env::var_os(KEY_VAR) {
+        Zeroizing::new(key.to_string_lossy().as_bytes().to_vec())
+    } else if let Some(path) = std::env::var_os(KEY_FILE_VAR) {
+        let mut key = Zeroizing::new(std::fs::read(path)?);
+        // Files usually end with a newline that isn't meant to be part of the key
+        while key.last().map(u8::is_ascii_whitespace).unwrap_or(false) {
+            key.pop();
+        }
+        key
+    } else {
+        return Ok(None);
+    };
+
+    if key.is_empty() {
+        return Err(Error::Usage(format!(
+            "the key in {} or {} is empty",
+            KEY_VAR, KEY_FILE_VAR
+        )));
+    }
+
+    Ok(Some(key))
+}
+
+fn mac(key: &[u8], store: &Store) -> Result<Hmac<Sha256>> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC takes keys of any length");
+    // Serializing a store always gives the same bytes, since its entries are kept in order
+    mac.update(&Zeroizing::new(serde_json::to_vec(store)?));
+    Ok(mac)
+}
+
+/// Wraps `store` in an envelope with its MAC
+pub fn seal<'a>(key: &[u8], store: &'a Store) -> Result<Sealed<'a>> {
+    let mac = mac(key, store)?.finalize().into_bytes();
+
+    Ok(Sealed {
+        mac: mac.iter().map(|byte| format!("{:02x}", byte)).collect(),
+        store,
+    })
+}
+
+/// Checks what was loaded from the store file at `path` against `key`, and returns the store
+pub fn open(path: &Path, stored: Stored, key: Option<&[u8]>) -> Result<Store> {
+    match (stored, key) {
+        (Stored::Plain(store), None) => Ok(store),
+        (Stored::Plain(_), Some(_)) => {
+            Err(Error::Integrity(path.to_path_buf(), "the store has no MAC"))
+        }
+        (Stored::Protected(_), None) => Err(Error::Integrity(
+            path.to_path_buf(),
+            "the store has a MAC, but no key was given to check it with",
+        )),
+        (Stored::Protected(envelope), Some(key)) => {
+            let expected = decode_hex(&envelope.mac)
+                .ok_or_else(|| Error::Integrity(path.to_path_buf(), "the MAC isn't valid hex"))?;
+
+            // verify_slice compares in constant time
+            mac(key, &envelope.store)?
+                .verify_slice(&expected)
+                .map_err(|_| {
+                    Error::Integrity(path.to_path_buf(), "the MAC doesn't match the store")
+                })?;
+
+            Ok(envelope.store)
+        }
+    }
+}
+
+/// The key, which `verify` and `sign` can't do without
+fn required_key() -> Result<Zeroizing<Vec<u8>>> {
+    key()?.ok_or_else(|| {
+        Error::Usage(format!(
+            "set {} or {} to the key to check the store with",
+            KEY_VAR, KEY_FILE_VAR
+        ))
+    })
+}
+
+/// The store files among `sources`, each of which has to exist
+fn store_files<'a>(sources: impl IntoIterator<Item = &'a Source>) -> Result<Vec<PathBuf>> {
+    let paths: Vec<PathBuf> = sources
+        .into_iter()
+        .filter_map(|source| match source {
+            Source::File(path) => Some(path.clone()),
+            _ => None,
+        })
+        .collect();
+    if paths.is_empty() {
+        return Err(Error::Usage("there is no store file to check".to_string()));
+    }
+
+    for path in &paths {
+        if !path.exists() {
+            return Err(Error::Integrity(
+                path.to_path_buf(),
+                "the store file doesn't exist",
+            ));
+        }
+    }
+
+    Ok(paths)
+}
+
+/// Checks every store file that `ctx` was loaded from, and prints the ones that are intact
+pub fn verify(ctx: &Context) -> Result<()> {
+    let key = required_key()?;
+
+    for path in store_files(ctx.layers.iter().map(|layer| &layer.source))? {
+        let stored = serde_json::from_reader(std::fs::File::open(&path)?)?;
+        open(&path, stored, Some(&key))?;
+        println!("{}: OK", path.display());
+    }
+
+    Ok(())
+}
+
+/// Adds a MAC to every store file in `opts` that doesn't have one yet, and checks the rest.
+///
+/// The files can't be loaded the usual way first, since that refuses files without a MAC.
+pub fn sign(opts: &StoreOpts) -> Result<()> {
+    let key = required_key()?;
+
+    for path in store_files(&Context::sources(opts)?)? {
+        // Nobody else may save the file between reading and signing it
+        let _lock = Lock::acquire(&path)?;
+
+        match serde_json::from_reader(std::fs::File::open(&path)?)? {
+            Stored::Plain(store) => {
+                store.check_version()?;
+                source::write_json(&path, &seal(&key, &store)?)?;
+                println!("{}: signed", path.display());
+            }
+            stored => {
+                open(&path, stored, Some(&key))?;
+                println!("{}: OK", path.display());
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    // A leftover digit at the end has no pair to `get`, so odd lengths are rejected too
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
@@ -0,0 +1,129 @@
+//! Stacks several sources into one store, so that a read-only base store can be shared while
+//! changes are kept somewhere else.
+//!
+//! Layers are looked up in the order they were given, so an entry in the first layer hides the
+//! same entry in any later layer. Changes are saved to the first layer that can be written to.
+//! Removing an entry that a later layer still has leaves a tombstone in the written layer, which
+//! hides the entry from the layers after it.
+
+use crate::error::Result;
+use crate::source::Source;
+use crate::store::{Diff, Store};
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+
+/// The metadata that lists the entries removed from the layers below
+pub const TOMBSTONES: &str = ".imposter/tombstones";
+
+/// A source, along with the store that was loaded from it
+#[derive(Debug, Clone)]
+pub struct Layer {
+    pub source: Source,
+    pub store: Store,
+}
+
+impl Layer {
+    pub fn load(source: Source) -> Result<Self> {
+        Ok(Self {
+            store: source.load()?,
+            source,
+        })
+    }
+
+    /// Reads the layer again, if anyone else could have changed it
+    pub fn reload(&mut self) -> Result<()> {
+        if let Source::File(_) = &self.source {
+            self.store = self.source.load()?;
+        }
+
+        Ok(())
+    }
+
+    fn tombstones(&self) -> Result<BTreeSet<PathBuf>> {
+        tombstones(&self.store)
+    }
+}
+
+fn tombstones(store: &Store) -> Result<BTreeSet<PathBuf>> {
+    Ok(match store.metadata(TOMBSTONES) {
+        Some(tombstones) => serde_json::from_str(tombstones)?,
+        None => BTreeSet::new(),
+    })
+}
+
+/// The store that is seen through all of the layers
+pub fn merge(layers: &[Layer]) -> Result<Store> {
+    let mut merged = Store::default();
+
+    // Start from the last layer, so that each layer covers the ones after it
+    for layer in layers.iter().rev() {
+        merged.apply(&Diff {
+            removed: layer.tombstones()?,
+            ..Diff::default()
+        });
+
+        // The entries are copied as they are, so that they keep their metadata
+        for (path, entry) in layer.store.entries() {
+            if path != Path::new(TOMBSTONES) {
+                merged.insert(path.to_path_buf(), entry.clone());
+            }
+        }
+    }
+
+    Ok(merged)
+}
+
+/// The layer that the merged entry at `path` comes from
+pub fn origin<'a>(layers: &'a [Layer], path: &Path) -> Result<Option<&'a Layer>> {
+    for layer in layers {
+        if layer.store.get(path)?.is_some() {
+            return Ok(Some(layer));
+        }
+        if layer.tombstones()?.contains(path) {
+            return Ok(None);
+        }
+    }
+
+    Ok(None)
+}
+
+/// Saves `diff` to the first layer that can be written to. If there isn't one, the changes are
+/// dropped.
+///
+/// Layers before the written layer can't be changed, so their entries always win. Reads are
+/// only counted for entries in the written layer, since the others can't be changed.
+pub fn save(layers: &[Layer], diff: &Diff) -> Result<()> {
+    let index = match layers.iter().position(|layer| layer.source.is_writable()) {
+        Some(index) => index,
+        None => return Ok(()),
+    };
+    let (written, below) = (&layers[index], &layers[index + 1..]);
+
+    // Start from what was last saved, so that changes made since it was loaded aren't lost. The
+    // lock stops anyone else saving until this is saved too.
+    let _lock = written.source.lock()?;
+    let mut store = written.source.load()?;
+    let mut tombstones = tombstones(&store)?;
+
+    for path in &diff.removed {
+        if below
+            .iter()
+            .any(|layer| matches!(layer.store.get(path), Ok(Some(_))))
+        {
+            tombstones.insert(path.clone());
+        }
+    }
+    for path in diff.set.keys() {
+        tombstones.remove(path);
+    }
+
+    store.apply(diff);
+    if tombstones.is_empty() {
+        store.remove_metadata(TOMBSTONES);
+    } else {
+        store.set_metadata(TOMBSTONES, serde_json::to_string(&tombstones)?);
+    }
+
+    // Only reading entries isn't worth a backup
+    written.source.save(&store, diff.changes_values())
+}
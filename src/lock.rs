@@ -0,0 +1,77 @@
+//! Advisory locks that stop processes saving the same store file at the same time.
+//!
+//! Saving reads the file back, applies the changes and replaces the file, so two processes doing
+//! that at once could each overwrite what the other just saved. The lock is on a `.lock` file
+//! next to the store, since the store file itself is replaced by every save.
+
+use crate::error::{Error, Result};
+use std::fs::{File, TryLockError};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+/// How many seconds to wait for a lock before giving up. The default is 10.
+pub const LOCK_TIMEOUT_VAR: &str = "IMPOSTER_PASS_LOCK_TIMEOUT";
+
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How long to wait between attempts to take a lock
+const RETRY_INTERVAL: Duration = Duration::from_millis(5);
+
+/// An exclusive lock on a store file, which is released when this is dropped
+#[derive(Debug)]
+pub struct Lock {
+    _file: File,
+}
+
+impl Lock {
+    /// Waits for the lock on the store file at `path`, up to the timeout
+    pub fn acquire(path: &Path) -> Result<Self> {
+        let lock_path = lock_path(path);
+        if let Some(dir) = lock_path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+
+        let mut options = std::fs::OpenOptions::new();
+        options.write(true).create(true).truncate(false);
+        #[cfg(target_family = "unix")]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+        let file = options.open(&lock_path)?;
+
+        let timeout = timeout()?;
+        let start = Instant::now();
+        loop {
+            match file.try_lock() {
+                Ok(()) => return Ok(Self { _file: file }),
+                Err(TryLockError::WouldBlock) if start.elapsed() < timeout => {
+                    std::thread::sleep(RETRY_INTERVAL)
+                }
+                Err(TryLockError::WouldBlock) => {
+                    return Err(Error::LockTimeout(lock_path, timeout))
+                }
+                Err(TryLockError::Error(err)) => return Err(err.into()),
+            }
+        }
+    }
+}
+
+/// The file that is locked for the store file at `path`
+fn lock_path(path: &Path) -> PathBuf {
+    let mut lock_path = path.as_os_str().to_owned();
+    lock_path.push(".lock");
+    PathBuf::from(lock_path)
+}
+
+fn timeout() -> Result<Duration> {
+    match std::env::var(LOCK_TIMEOUT_VAR) {
+        Ok(timeout) => timeout.parse().map(Duration::from_secs_f64).map_err(|_| {
+            Error::Usage(format!(
+                "{} must be a number of seconds, not {:?}",
+                LOCK_TIMEOUT_VAR, timeout
+            ))
+        }),
+        Err(_) => Ok(DEFAULT_TIMEOUT),
+    }
+}
@@ -0,0 +1,81 @@
+//! What is known about each entry besides its value: when it was created and last changed, what
+//! changed it, and when and how often it has been read.
+//!
+//! Entries without any of this are saved as plain strings, just like stores that were saved
+//! before there was any. Entries with some are saved as an object, like
+//! `{"value": "hunter2\n", "created": "2024-01-01T00:00:00Z", "reads": 3}`.
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Default, Debug, Serialize, Deserialize, Hash, PartialEq, Eq)]
+pub struct Meta {
+    /// When the entry was first set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created: Option<DateTime<Utc>>,
+
+    /// When the value was last set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub updated: Option<DateTime<Utc>>,
+
+    /// The command, or the fooled program, that last set the value, like `pass insert`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub writer: Option<String>,
+
+    /// When the value was last read
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_read: Option<DateTime<Utc>>,
+
+    /// How many times the value has been read
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub reads: u64,
+}
+
+fn is_zero(count: &u64) -> bool {
+    *count == 0
+}
+
+/// Reads of an entry that are yet to be added to its metadata
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Reads {
+    pub count: u64,
+
+    /// When the last of the reads happened
+    pub last: DateTime<Utc>,
+}
+
+impl Meta {
+    pub fn is_empty(&self) -> bool {
+        *self == Meta::default()
+    }
+
+    /// Records that `writer` set the value at `at`
+    pub fn written(&mut self, writer: Option<&str>, at: DateTime<Utc>) {
+        self.created.get_or_insert(at);
+        self.updated = Some(at);
+        self.writer = writer.map(str::to_string);
+    }
+
+    pub fn read(&mut self, reads: Reads) {
+        self.reads += reads.count;
+        self.last_read = self.last_read.max(Some(reads.last));
+    }
+
+    /// The reads since the metadata was `old`, if there were any
+    pub fn reads_since(&self, old: Option<&Meta>) -> Option<Reads> {
+        let count = self
+            .reads
+            .saturating_sub(old.map(|old| old.reads).unwrap_or_default());
+
+        match (count, self.last_read) {
+            (0, _) | (_, None) => None,
+            (count, Some(last)) => Some(Reads { count, last }),
+        }
+    }
+}
+
+/// A time as `ls --long` and `show --meta` print it, or `-` if it isn't known
+pub fn format_time(time: Option<DateTime<Utc>>) -> String {
+    time.map(|time| time.to_rfc3339_opts(SecondsFormat::Secs, true))
+        .unwrap_or_else(|| "-".to_string())
+}
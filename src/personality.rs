@@ -0,0 +1,86 @@
+use std::ffi::OsStr;
+use std::fmt::{self, Display};
+use std::path::Path;
+use std::str::FromStr;
+
+/// The programs that imposter-pass knows how to impersonate.
+///
+/// The personality is picked from the name the executable was invoked as, so installing a
+/// symlink (or hardlink) called `pass` that points at imposter-pass is enough to make it behave
+/// like pass.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Personality {
+    /// Invoked under its own name (or any name we don't recognize)
+    ImposterPass,
+
+    /// Invoked as `pass`
+    Pass,
+
+    /// Invoked as `passage`, which takes the same arguments as pass
+    Passage,
+
+    /// Invoked as `gopass`
+    Gopass,
+
+    /// Invoked as `secret-tool`, the libsecret command line tool
+    SecretTool,
+
+    /// Invoked as `gpg`, for programs that check for a key before using pass
+    Gpg,
+}
+
+impl Personality {
+    /// The names of the personalities that fool can put on the path
+    pub const SHIMS: [&'static str; 5] = ["pass", "passage", "gopass", "secret-tool", "gpg"];
+
+    /// Works out the personality from an `argv[0]`
+    pub fn from_arg0(arg0: impl AsRef<OsStr>) -> Self {
+        Path::new(arg0.as_ref())
+            .file_name()
+            .and_then(OsStr::to_str)
+            .and_then(|name| name.parse().ok())
+            .unwrap_or(Personality::ImposterPass)
+    }
+
+    /// Works out the personality of the current process
+    pub fn detect() -> Self {
+        std::env::args_os()
+            .next()
+            .map(Self::from_arg0)
+            .unwrap_or(Personality::ImposterPass)
+    }
+
+    /// The name that a program should be invoked as to get this personality
+    pub fn name(self) -> &'static str {
+        match self {
+            Personality::ImposterPass => "imposter-pass",
+            Personality::Pass => "pass",
+            Personality::Passage => "passage",
+            Personality::Gopass => "gopass",
+            Personality::SecretTool => "secret-tool",
+            Personality::Gpg => "gpg",
+        }
+    }
+}
+
+impl Display for Personality {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Personality {
+    type Err = String;
+
+    /// Parses the name of one of the [`Personality::SHIMS`]
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "pass" => Ok(Personality::Pass),
+            "passage" => Ok(Personality::Passage),
+            "gopass" => Ok(Personality::Gopass),
+            "secret-tool" => Ok(Personality::SecretTool),
+            "gpg" => Ok(Personality::Gpg),
+            _ => Err(format!("imposter-pass can't impersonate {:?}", s)),
+        }
+    }
+}
@@ -0,0 +1,327 @@
+use crate::error::{Error, Result};
+use crate::store::{Diff, Store};
+use serde::{Deserialize, Serialize};
+use std::fmt::{self, Display};
+use std::path::{Path, PathBuf};
+
+/// The environment variable that holds the path to the policy file
+pub const POLICY_VAR: &str = "IMPOSTER_PASS_POLICY";
+
+/// If this environment variable is set to `true`, the store is read-only regardless of the policy
+pub const READ_ONLY_VAR: &str = "IMPOSTER_PASS_READ_ONLY";
+
+/// The operations that a policy can allow or deny
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Operation {
+    Show,
+    Insert,
+    Rm,
+    Generate,
+}
+
+impl Operation {
+    /// Whether this operation changes the store
+    pub fn writes(self) -> bool {
+        self != Operation::Show
+    }
+}
+
+impl Display for Operation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Operation::Show => "show",
+            Operation::Insert => "insert",
+            Operation::Rm => "rm",
+            Operation::Generate => "generate",
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Access {
+    #[default]
+    Allow,
+    Deny,
+}
+
+/// Allows and/or denies operations on every entry under `prefix`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Rule {
+    pub prefix: PathBuf,
+
+    #[serde(default)]
+    pub allow: Vec<Operation>,
+
+    #[serde(default)]
+    pub deny: Vec<Operation>,
+}
+
+impl Rule {
+    /// What this rule says about `op`, if it says anything at all
+    fn access(&self, op: Operation) -> Option<Access> {
+        if self.deny.contains(&op) {
+            Some(Access::Deny)
+        } else if self.allow.contains(&op) {
+            Some(Access::Allow)
+        } else {
+            None
+        }
+    }
+}
+
+/// Decides which entries a program is allowed to see and change.
+///
+/// For each operation, the rule with the longest prefix that mentions the operation wins. If no
+/// rule mentions it, `default` is used. A rule that both allows and denies an operation denies
+/// it. In read-only mode, every operation except `show` is denied.
+///
+/// An example policy file, which only lets a program read the entries under `email/`:
+///
+/// ```json
+/// {
+///     "default": "deny",
+///     "rules": [{ "prefix": "email", "allow": ["show"] }]
+/// }
+/// ```
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Policy {
+    pub read_only: bool,
+    pub default: Access,
+    pub rules: Vec<Rule>,
+}
+
+impl Policy {
+    /// Loads the policy file at `path`, or allows everything if there is no policy.
+    ///
+    /// Setting IMPOSTER_PASS_READ_ONLY to `true` makes the policy read-only.
+    pub fn load(path: Option<&Path>) -> Result<Self> {
+        let mut policy = match path {
+            Some(path) => serde_json::from_reader(std::fs::File::open(path)?)?,
+            None => Self::default(),
+        };
+
+        if std::env::var(READ_ONLY_VAR)
+            .map(|value| value.eq_ignore_ascii_case("true"))
+            .unwrap_or(false)
+        {
+            policy.read_only = true;
+        }
+
+        for rule in &mut policy.rules {
+            rule.prefix = Store::normalize(&rule.prefix)?;
+        }
+
+        Ok(policy)
+    }
+
+    /// Whether `op` is allowed on `path`, which is matched the way the store would name it, so
+    /// that `./secret` and `/secret` are both `secret`. Paths that can't be an entry are denied.
+    pub fn access(&self, op: Operation, path: impl AsRef<Path>) -> Access {
+        if self.read_only && op.writes() {
+            return Access::Deny;
+        }
+
+        let path = match Store::normalize(path) {
+            Ok(path) => path,
+            Err(_) => return Access::Deny,
+        };
+
+        self.rules
+            .iter()
+            .filter(|rule| path.starts_with(&rule.prefix))
+            .filter_map(|rule| Some((rule.prefix.components().count(), rule.access(op)?)))
+            // On ties, the later rule wins
+            .max_by_key(|(specificity, _)| *specificity)
+            .map(|(_, access)| access)
+            .unwrap_or(self.default)
+    }
+
+    /// Fails with a permission error if `op` is not allowed on `path`
+    pub fn check(&self, op: Operation, path: impl AsRef<Path>) -> Result<()> {
+        match self.access(op, &path) {
+            Access::Allow => Ok(()),
+            Access::Deny => Err(Error::AccessDenied(op, path.as_ref().to_path_buf())),
+        }
+    }
+
+    /// Fails with a permission error if `op` is not allowed on every entry in or under `path`
+    pub fn check_all(&self, op: Operation, store: &Store, path: impl AsRef<Path>) -> Result<()> {
+        self.check(op, &path)?;
+
+        for entry in store.list_paths(Some(&path))? {
+            self.check(op, entry)?;
+        }
+
+        Ok(())
+    }
+
+    /// Fails with a permission error unless every change in `diff` is allowed. Entries that are
+    /// set count as inserted, and entries that were read as shown.
+    pub fn check_diff(&self, diff: &Diff) -> Result<()> {
+        for path in diff.set.keys() {
+            self.check(Operation::Insert, path)?;
+        }
+        for path in &diff.removed {
+            self.check(Operation::Rm, path)?;
+        }
+        for path in diff.read.keys() {
+            self.check(Operation::Show, path)?;
+        }
+
+        Ok(())
+    }
+
+    /// Whether the entry at `path` may be shown. Metadata, like `.gpg-id`, always may.
+    pub fn shows(&self, path: &Path) -> bool {
+        Store::is_metadata(path) || self.access(Operation::Show, path) == Access::Allow
+    }
+
+    /// A copy of `store` with only the entries that may be shown, and its metadata.
+    ///
+    /// Hidden entries behave exactly as if they did not exist.
+    pub fn visible(&self, store: &Store) -> Result<Store> {
+        let mut visible = Store::default();
+
+        for (path, entry) in store.entries() {
+            if self.shows(path) {
+                visible.insert(path.to_path_buf(), entry.clone());
+            }
+        }
+
+        Ok(visible)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn deny_secret() -> Policy {
+        Policy {
+            rules: vec![Rule {
+                prefix: "secret".into(),
+                allow: vec![],
+                deny: vec![Operation::Show, Operation::Insert],
+            }],
+            ..Policy::default()
+        }
+    }
+
+    #[test]
+    fn longest_prefix_wins() {
+        let policy = Policy {
+            default: Access::Deny,
+            rules: vec![
+                Rule {
+                    prefix: "email".into(),
+                    allow: vec![Operation::Show, Operation::Insert],
+                    deny: vec![],
+                },
+                Rule {
+                    prefix: "email/work".into(),
+                    allow: vec![],
+                    deny: vec![Operation::Insert],
+                },
+            ],
+            ..Policy::default()
+        };
+
+        assert_eq!(policy.access(Operation::Show, "email/home"), Access::Allow);
+        assert_eq!(
+            policy.access(Operation::Insert, "email/home"),
+            Access::Allow
+        );
+        assert_eq!(
+            policy.access(Operation::Show, "email/work/x"),
+            Access::Allow
+        );
+        assert_eq!(
+            policy.access(Operation::Insert, "email/work/x"),
+            Access::Deny
+        );
+        assert_eq!(policy.access(Operation::Show, "emails"), Access::Deny);
+        assert_eq!(policy.access(Operation::Rm, "email/home"), Access::Deny);
+    }
+
+    #[test]
+    fn read_only_denies_writes() {
+        let policy = Policy {
+            read_only: true,
+            ..Policy::default()
+        };
+
+        assert_eq!(policy.access(Operation::Show, "x"), Access::Allow);
+        assert_eq!(policy.access(Operation::Insert, "x"), Access::Deny);
+        assert_eq!(policy.access(Operation::Rm, "x"), Access::Deny);
+    }
+
+    #[test]
+    fn paths_are_normalized() {
+        let policy = deny_secret();
+
+        for path in &[
+            "secret/x",
+            "./secret/x",
+            "/secret/x",
+            "secret/./x",
+            "secret//x",
+        ] {
+            assert_eq!(
+                policy.access(Operation::Show, path),
+                Access::Deny,
+                "{}",
+                path
+            );
+            assert_eq!(
+                policy.access(Operation::Insert, path),
+                Access::Deny,
+                "{}",
+                path
+            );
+            assert!(policy.check(Operation::Insert, path).is_err(), "{}", path);
+        }
+
+        assert_eq!(policy.access(Operation::Show, "./public/x"), Access::Allow);
+    }
+
+    #[test]
+    fn parent_components_are_denied() {
+        let policy = deny_secret();
+
+        assert_eq!(
+            policy.access(Operation::Show, "a/../secret/x"),
+            Access::Deny
+        );
+        assert_eq!(policy.access(Operation::Show, "a/../public"), Access::Deny);
+        assert!(policy.check(Operation::Show, "../x").is_err());
+    }
+
+    #[test]
+    fn check_all_checks_entries_under_path() {
+        let policy = deny_secret();
+        let store: Store = r#"{"secret/x": "1", "public/y": "2"}"#.parse().unwrap();
+
+        assert!(policy
+            .check_all(Operation::Insert, &store, "public")
+            .is_ok());
+        assert!(policy.check_all(Operation::Insert, &store, "./").is_err());
+        assert!(policy.check_all(Operation::Insert, &store, "/").is_err());
+    }
+
+    #[test]
+    fn visible_hides_denied_entries() {
+        let policy = deny_secret();
+        let store: Store = r#"{"secret/x": "1", "public/y": "2", ".gpg-id": "k"}"#
+            .parse()
+            .unwrap();
+        let visible = policy.visible(&store).unwrap();
+
+        let paths: Vec<_> = visible.list_paths(None::<&Path>).unwrap().collect();
+        assert_eq!(paths, vec![Path::new("public/y")]);
+        assert_eq!(visible.metadata(".gpg-id"), Some("k"));
+    }
+}
@@ -0,0 +1,198 @@
+//! Fills in templates with entries from the store, so that config files can be generated
+//! without handing the whole store to whatever reads them.
+//!
+//! A placeholder looks like `{{ pass "email/bridge" }}`, which is replaced by the password (the
+//! first line) of that entry. Options pick out something else:
+//!
+//! - `line=N` is the Nth line of the entry, counting the password as line 1
+//! - `field=key` is the value of a `key: value` line
+//!
+//! Anything else in `{{ }}` that doesn't start with `pass` is left alone, so templates can
+//! contain other template syntax.
+
+use crate::audit;
+use crate::context::Context;
+use crate::error::{Error, Result};
+use crate::policy::Operation;
+use crate::store::{self, Store};
+use std::io::{IsTerminal, Read, Write};
+use std::path::{Path, PathBuf};
+
+const OPEN: &str = "{{";
+const CLOSE: &str = "}}";
+
+/// What a placeholder is replaced with
+#[derive(Debug, PartialEq, Eq)]
+enum Part {
+    Password,
+    Line(usize),
+    Field(String),
+}
+
+#[derive(Debug, PartialEq, Eq)]
+struct Placeholder {
+    name: PathBuf,
+    part: Part,
+}
+
+/// Parses what is between the braces of a placeholder, or returns `None` if it isn't a `pass`
+/// placeholder at all
+fn parse_placeholder(text: &str) -> Option<std::result::Result<Placeholder, String>> {
+    let text = text.trim();
+    let rest = text.strip_prefix("pass")?;
+    if !rest.starts_with(char::is_whitespace) {
+        return None;
+    }
+
+    Some(parse_arguments(rest))
+}
+
+/// Parses the entry name and options that follow `pass`
+fn parse_arguments(rest: &str) -> std::result::Result<Placeholder, String> {
+    let rest = rest.trim_start();
+    let rest = rest
+        .strip_prefix('"')
+        .ok_or_else(|| "the entry name must be in double quotes".to_string())?;
+
+    // The name ends at the first quote that isn't escaped
+    let mut name = String::new();
+    let mut chars = rest.char_indices();
+    let end = loop {
+        match chars.next() {
+            Some((_, '\\')) => match chars.next() {
+                Some((_, c)) => name.push(c),
+                None => return Err("unterminated entry name".to_string()),
+            },
+            Some((i, '"')) => break i,
+            Some((_, c)) => name.push(c),
+            None => return Err("unterminated entry name".to_string()),
+        }
+    };
+
+    let mut part = Part::Password;
+    for option in rest[end + 1..].split_whitespace() {
+        part = match option.split_once('=') {
+            Some(("line", line)) => match line.parse() {
+                Ok(line) if line > 0 => Part::Line(line),
+                _ => return Err(format!("invalid line number {:?}", line)),
+            },
+            Some(("field", field)) => Part::Field(field.trim_matches('"').to_string()),
+            _ => return Err(format!("unknown option {:?}", option)),
+        };
+    }
+
+    Ok(Placeholder {
+        name: PathBuf::from(name),
+        part,
+    })
+}
+
+/// Looks up what a placeholder stands for
+fn lookup(ctx: &Context, visible: &Store, placeholder: &Placeholder) -> Result<String> {
+    let name = &placeholder.name;
+
+    let result = ctx.policy.check(Operation::Show, name).and_then(|_| {
+        let value = match visible.get(name)? {
+            Some(value) => value,
+            None if visible.list(Some(name))?.next().is_some() => {
+                return Err(Error::Usage(format!("{} is a folder", name.display())))
+            }
+            None => return Err(Error::NotInStore(name.clone())),
+        };
+
+        let (password, fields) = store::parse_entry(value);
+        match &placeholder.part {
+            Part::Password => Ok(password.to_string()),
+            Part::Line(line) => value
+                .lines()
+                .nth(line - 1)
+                .map(str::to_string)
+                .ok_or_else(|| Error::Usage(format!("{} has no line {}", name.display(), line))),
+            Part::Field(field) => fields
+                .get(field.as_str())
+                .map(|value| value.to_string())
+                .ok_or_else(|| {
+                    Error::Usage(format!("{} has no field {:?}", name.display(), field))
+                }),
+        }
+    });
+
+    audit::record("render", Some(name), result)
+}
+
+/// Fills in every placeholder in `template`, and adds the entry of each one to `read`
+fn render_str(
+    ctx: &Context,
+    template: &str,
+    source: &Path,
+    read: &mut Vec<PathBuf>,
+) -> Result<String> {
+    let visible = ctx.policy.visible(&ctx.store)?;
+    let mut rendered = String::with_capacity(template.len());
+    let mut rest = template;
+
+    while let Some(start) = rest.find(OPEN) {
+        let line = template[..template.len() - rest.len() + start]
+            .matches('\n')
+            .count()
+            + 1;
+        let at =
+            |message: String| Error::Usage(format!("{}:{}: {}", source.display(), line, message));
+
+        rendered.push_str(&rest[..start]);
+        let after_open = &rest[start + OPEN.len()..];
+
+        let end = match after_open.find(CLOSE) {
+            Some(end) => end,
+            None => return Err(at("unclosed placeholder".to_string())),
+        };
+
+        match parse_placeholder(&after_open[..end]) {
+            Some(Ok(placeholder)) => {
+                let value =
+                    lookup(ctx, &visible, &placeholder).map_err(|err| at(err.to_string()))?;
+                rendered.push_str(&value);
+                read.push(placeholder.name);
+            }
+            Some(Err(message)) => return Err(at(message)),
+            None => {
+                rendered.push_str(&rest[start..start + OPEN.len() + end + CLOSE.len()]);
+            }
+        }
+
+        rest = &after_open[end + CLOSE.len()..];
+    }
+    rendered.push_str(rest);
+
+    Ok(rendered)
+}
+
+/// Renders `template` (or stdin, if it is `-`) to stdout.
+///
+/// Nothing is written unless every placeholder could be filled in. In `strict` mode, the output
+/// is refused if it would go to a terminal, where the secrets could be seen.
+pub fn render(ctx: &Context, template: &Path, strict: bool) -> Result<()> {
+    if strict && std::io::stdout().is_terminal() {
+        return Err(Error::Usage(
+            "refusing to render secrets to a terminal in strict mode".to_string(),
+        ));
+    }
+
+    let mut text = String::new();
+    if template == Path::new("-") {
+        std::io::stdin().read_to_string(&mut text)?;
+    } else {
+        std::fs::File::open(template)?.read_to_string(&mut text)?;
+    }
+
+    let mut read = Vec::new();
+    let rendered = render_str(ctx, &text, template, &mut read)?;
+
+    let mut stdout = std::io::stdout();
+    stdout.write_all(rendered.as_bytes())?;
+    stdout.flush()?;
+
+    ctx.record_reads(read.iter().map(PathBuf::as_path));
+
+    Ok(())
+}
@@ -0,0 +1,300 @@
+//! Sealed stores, which are encrypted with a passphrase so that a store can be handed to a
+//! container in IMPOSTER_PASS_STORE without carrying the secrets in plain text.
+//!
+//! A sealed store is armored like
+//!
+//! ```text
+//! -----BEGIN IMPOSTER-PASS SEALED STORE-----
+//! <base64>
+//! -----END IMPOSTER-PASS SEALED STORE-----
+//! ```
+//!
+//! The base64 holds a header (the format version, the Argon2id parameters, a salt and a nonce)
+//! followed by the store as json, encrypted with ChaCha20-Poly1305 using a key derived from the
+//! passphrase with Argon2id. The header is authenticated along with the store, so it can't be
+//! changed either.
+
+use crate::context::Context;
+use crate::error::{Error, Result};
+use crate::policy::Operation;
+use crate::store::Store;
+use argon2::{Algorithm, Argon2, Params, Version};
+use base64::Engine;
+use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use rand::RngCore;
+use std::io::IsTerminal;
+use std::path::Path;
+use zeroize::Zeroizing;
+
+/// The passphrase to seal and open stores with
+pub const PASSPHRASE_VAR: &str = "IMPOSTER_PASS_PASSPHRASE";
+
+/// A file holding the passphrase, used if IMPOSTER_PASS_PASSPHRASE isn't set
+pub const PASSPHRASE_FILE_VAR: &str = "IMPOSTER_PASS_PASSPHRASE_FILE";
+
+const BEGIN: &str = "-----BEGIN IMPOSTER-PASS SEALED STORE-----";
+const END: &str = "-----END IMPOSTER-PASS SEALED STORE-----";
+
+/// The first byte of every sealed store, so that the format can change later
+const VERSION: u8 = 1;
+
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+/// The version, the three Argon2 parameters, the salt and the nonce
+const HEADER_LEN: usize = 1 + 3 * 4 + SALT_LEN + NONCE_LEN;
+
+/// How many characters of base64 to put on each line
+const LINE_LEN: usize = 64;
+
+/// The most memory (in KiB), passes and lanes that opening a sealed store may use. The
+/// parameters are read before the header can be authenticated, so without a limit a crafted
+/// store could ask for any amount of memory. These are well above the defaults stores are sealed
+/// with.
+const MAX_M_COST: u32 = 256 * 1024;
+const MAX_T_COST: u32 = 16;
+const MAX_P_COST: u32 = 8;
+
+/// Whether `text` looks like a sealed store
+pub fn is_sealed(text: &str) -> bool {
+    text.trim_start().starts_with(BEGIN)
+}
+
+/// The passphrase from IMPOSTER_PASS_PASSPHRASE or IMPOSTER_PASS_PASSPHRASE_FILE, or `None` if
+/// neither is set
+fn passphrase() -> Result<Option<Zeroizing<Vec<u8>>>> {
+    let passphrase = if let Some(passphrase) = std::env::var_os(PASSPHRASE_VAR) {
+        Zeroizing::new(passphrase.to_string_lossy().as_bytes().to_vec())
+    } else if let Some(path) = std::env::var_os(PASSPHRASE_FILE_VAR) {
+        let mut passphrase = Zeroizing::new(std::fs::read(path)?);
+        // Files usually end with a newline that isn't meant to be part of the passphrase
+        while passphrase.last() == Some(&b'\n') || passphrase.last() == Some(&b'\r') {
+            passphrase.pop();
+        }
+        passphrase
+    } else {
+        return Ok(None);
+    };
+
+    if passphrase.is_empty() {
+        return Err(Error::Usage("the passphrase is empty".to_string()));
+    }
+
+    Ok(Some(passphrase))
+}
+
+fn no_passphrase() -> Error {
+    Error::Usage(format!(
+        "set {} or {} to the passphrase of the sealed store",
+        PASSPHRASE_VAR, PASSPHRASE_FILE_VAR
+    ))
+}
+
+fn derive_key(passphrase: &[u8], params: Params, salt: &[u8]) -> Result<Zeroizing<[u8; 32]>> {
+    let mut key = Zeroizing::new([0; 32]);
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+        .hash_password_into(passphrase, salt, &mut *key)
+        .map_err(|_| Error::Sealed("the key could not be derived from the passphrase"))?;
+
+    Ok(key)
+}
+
+/// Encrypts `store` with `passphrase`, and armors it
+pub fn seal(store: &Store, passphrase: &[u8]) -> Result<String> {
+    let params = Params::default();
+    let mut salt = [0; SALT_LEN];
+    let mut nonce = [0; NONCE_LEN];
+    rand::rngs::OsRng.fill_bytes(&mut salt);
+    rand::rngs::OsRng.fill_bytes(&mut nonce);
+
+    let mut sealed = Vec::with_capacity(HEADER_LEN);
+    sealed.push(VERSION);
+    for param in [params.m_cost(), params.t_cost(), params.p_cost()] {
+        sealed.extend_from_slice(&param.to_le_bytes());
+    }
+    sealed.extend_from_slice(&salt);
+    sealed.extend_from_slice(&nonce);
+
+    let key = derive_key(passphrase, params, &salt)?;
+    let plaintext = Zeroizing::new(serde_json::to_vec(store)?);
+    let ciphertext = ChaCha20Poly1305::new(Key::from_slice(&*key))
+        .encrypt(
+            Nonce::from_slice(&nonce),
+            Payload {
+                msg: &plaintext,
+                aad: &sealed,
+            },
+        )
+        .map_err(|_| Error::Sealed("the store could not be encrypted"))?;
+    sealed.extend_from_slice(&ciphertext);
+
+    let encoded = base64::engine::general_purpose::STANDARD.encode(&sealed);
+    let mut armored = String::from(BEGIN);
+    for line in encoded.as_bytes().chunks(LINE_LEN) {
+        armored.push('\n');
+        armored.push_str(&String::from_utf8_lossy(line));
+    }
+    armored.push('\n');
+    armored.push_str(END);
+    armored.push('\n');
+
+    Ok(armored)
+}
+
+/// Decrypts the armored store in `text`, with the passphrase from the environment
+pub fn open(text: &str) -> Result<Store> {
+    open_with(text, &passphrase()?.ok_or_else(no_passphrase)?)
+}
+
+fn open_with(text: &str, passphrase: &[u8]) -> Result<Store> {
+    let body = text
+        .trim()
+        .strip_prefix(BEGIN)
+        .and_then(|body| body.strip_suffix(END))
+        .ok_or(Error::Sealed("the armor is broken"))?;
+    let body: String = body.split_whitespace().collect();
+    let sealed = base64::engine::general_purpose::STANDARD
+        .decode(body)
+        .map_err(|_| Error::Sealed("the armor is broken"))?;
+
+    if sealed.len() < HEADER_LEN {
+        return Err(Error::Sealed("the sealed store is too short"));
+    }
+    let (header, ciphertext) = sealed.split_at(HEADER_LEN);
+    if header[0] != VERSION {
+        return Err(Error::Sealed(
+            "it was sealed in a format this imposter-pass doesn't know",
+        ));
+    }
+
+    let param = |index: usize| {
+        let start = 1 + index * 4;
+        u32::from_le_bytes([
+            header[start],
+            header[start + 1],
+            header[start + 2],
+            header[start + 3],
+        ])
+    };
+    let (m_cost, t_cost, p_cost) = (param(0), param(1), param(2));
+    if m_cost > MAX_M_COST || t_cost > MAX_T_COST || p_cost > MAX_P_COST {
+        return Err(Error::Sealed(
+            "the key parameters ask for too much memory or time",
+        ));
+    }
+    let params = Params::new(m_cost, t_cost, p_cost, None)
+        .map_err(|_| Error::Sealed("the key parameters are invalid"))?;
+    let salt = &header[1 + 3 * 4..1 + 3 * 4 + SALT_LEN];
+    let nonce = &header[HEADER_LEN - NONCE_LEN..];
+
+    let key = derive_key(passphrase, params, salt)?;
+    let plaintext = Zeroizing::new(
+        ChaCha20Poly1305::new(Key::from_slice(&*key))
+            .decrypt(
+                Nonce::from_slice(nonce),
+                Payload {
+                    msg: ciphertext,
+                    aad: header,
+                },
+            )
+            .map_err(|_| Error::Sealed("wrong passphrase, or the sealed store was changed"))?,
+    );
+
+    Ok(serde_json::from_slice(&plaintext)?)
+}
+
+/// Prints the store, sealed with the passphrase from the environment or one that is asked for.
+///
+/// The policy must allow every entry to be shown, since the sealed store holds all of them.
+pub fn run(ctx: &Context) -> Result<()> {
+    ctx.policy
+        .check_all(Operation::Show, &ctx.store, Path::new(""))?;
+
+    let passphrase = match passphrase()? {
+        Some(passphrase) => passphrase,
+        None if std::io::stdin().is_terminal() => {
+            let passphrase = Zeroizing::new(rpassword::read_password_from_tty(Some(
+                "Enter passphrase for the sealed store: ",
+            ))?);
+            let retyped = Zeroizing::new(rpassword::read_password_from_tty(Some(
+                "Retype passphrase for the sealed store: ",
+            ))?);
+            if passphrase != retyped {
+                return Err(Error::Usage(
+                    "the entered passphrases do not match".to_string(),
+                ));
+            }
+            if passphrase.is_empty() {
+                return Err(Error::Usage("the passphrase is empty".to_string()));
+            }
+
+            Zeroizing::new(passphrase.as_bytes().to_vec())
+        }
+        None => return Err(no_passphrase()),
+    };
+
+    print!("{}", seal(&ctx.store, &passphrase)?);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sealed_store() -> String {
+        let store: Store = r#"{"a": "secret"}"#.parse().unwrap();
+        seal(&store, b"passphrase").unwrap()
+    }
+
+    /// `armored` with its header changed by `change`
+    fn with_header(armored: &str, change: impl FnOnce(&mut [u8])) -> String {
+        let body: String = armored
+            .trim()
+            .strip_prefix(BEGIN)
+            .and_then(|body| body.strip_suffix(END))
+            .unwrap()
+            .split_whitespace()
+            .collect();
+        let mut sealed = base64::engine::general_purpose::STANDARD
+            .decode(body)
+            .unwrap();
+        change(&mut sealed[..HEADER_LEN]);
+
+        format!(
+            "{}\n{}\n{}\n",
+            BEGIN,
+            base64::engine::general_purpose::STANDARD.encode(&sealed),
+            END
+        )
+    }
+
+    #[test]
+    fn round_trip() {
+        let store = open_with(&sealed_store(), b"passphrase").unwrap();
+        assert_eq!(store.get("a").unwrap(), Some("secret"));
+
+        assert!(open_with(&sealed_store(), b"wrong").is_err());
+    }
+
+    #[test]
+    fn costly_parameters_are_refused() {
+        let armored = sealed_store();
+
+        for (index, value) in [
+            (0, u32::MAX),
+            (0, MAX_M_COST + 1),
+            (1, 1 << 20),
+            (2, 1 << 16),
+        ] {
+            let crafted = with_header(&armored, |header| {
+                let start = 1 + index * 4;
+                header[start..start + 4].copy_from_slice(&value.to_le_bytes());
+            });
+
+            match open_with(&crafted, b"passphrase") {
+                Err(Error::Sealed(message)) => assert!(message.contains("too much"), "{}", message),
+                other => panic!("parameter {} = {} gave {:?}", index, value, other.map(drop)),
+            }
+        }
+    }
+}
@@ -0,0 +1,99 @@
+//! A string type for passwords and other entry values, which wipes its memory once it is done
+//! with, so that secrets don't linger in freed memory (or in a core dump, or swap) for longer
+//! than they are needed.
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::ops::Deref;
+use zeroize::{Zeroize, Zeroizing};
+
+/// A string that is overwritten with zeros when it is dropped.
+///
+/// Its `Debug` output is masked, so that secrets can't end up in logs by accident. Use `Deref`
+/// to get at the value.
+#[derive(Clone, Default, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Secret(String);
+
+impl Secret {
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// Takes over `bytes` without copying them, unless they aren't valid UTF-8, in which case
+    /// they are converted like `String::from_utf8_lossy` does and then wiped
+    pub fn from_bytes(bytes: Vec<u8>) -> Self {
+        match String::from_utf8(bytes) {
+            Ok(text) => Self(text),
+            Err(err) => {
+                let mut bytes = err.into_bytes();
+                let secret = Self(String::from_utf8_lossy(&bytes).into_owned());
+                bytes.zeroize();
+                secret
+            }
+        }
+    }
+
+    /// Reads a line from `reader` into a new secret, including the line ending
+    pub fn read_line(reader: &mut impl std::io::BufRead) -> std::io::Result<Self> {
+        // Leave room for the whole line, since the old buffer isn't wiped when it has to grow
+        let mut buffer = Zeroizing::new(Vec::with_capacity(1024));
+        reader.read_until(b'\n', &mut buffer)?;
+        Ok(Self::from_bytes(std::mem::take(&mut *buffer)))
+    }
+
+    /// Reads everything from `reader` into a new secret
+    pub fn read_to_end(reader: &mut impl std::io::Read) -> std::io::Result<Self> {
+        let mut buffer = Zeroizing::new(Vec::with_capacity(4096));
+        reader.read_to_end(&mut buffer)?;
+        Ok(Self::from_bytes(std::mem::take(&mut *buffer)))
+    }
+
+    /// Joins `parts` into one secret, without leaving partial copies around
+    pub fn concat(parts: &[&str]) -> Self {
+        let mut secret = String::with_capacity(parts.iter().map(|part| part.len()).sum());
+        for part in parts {
+            secret.push_str(part);
+        }
+
+        Self(secret)
+    }
+}
+
+impl Drop for Secret {
+    fn drop(&mut self) {
+        self.0.zeroize();
+    }
+}
+
+impl Deref for Secret {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl AsRef<str> for Secret {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl From<String> for Secret {
+    fn from(value: String) -> Self {
+        Self(value)
+    }
+}
+
+impl From<&str> for Secret {
+    fn from(value: &str) -> Self {
+        Self(value.to_string())
+    }
+}
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("Secret(********)")
+    }
+}
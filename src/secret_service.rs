@@ -0,0 +1,1077 @@
+//! A freedesktop Secret Service (org.freedesktop.secrets) provider backed by the store.
+//!
+//! Every collection is a folder in the store. The default collection, `login`, is the folder
+//! that the secret-tool shim uses, so that both of them see the same secrets. Any other
+//! collection lives at `secret-service/<collection>`. Inside of a collection, items are stored
+//! by their attributes, in the same way as secret-tool stores them.
+//!
+//! Object paths may only contain ASCII letters, digits and underscores, so anything else in a
+//! collection name is escaped as `_` and two hex digits, and item paths are hex encoded.
+//!
+//! Only the `plain` session algorithm is supported, and nothing is ever locked. Item labels are
+//! accepted but not kept.
+
+use crate::audit;
+use crate::context::Context;
+use crate::error::{Error, Result};
+use crate::policy::Operation;
+use crate::secret_tool::{attributes_path, path_attributes};
+use crate::store::Store;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::convert::{TryFrom, TryInto};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use zbus::blocking::Connection;
+use zbus::message::{Header, Message, Type};
+use zbus::zvariant::{ObjectPath, OwnedObjectPath, OwnedValue, Value};
+
+/// The well known name of the Secret Service
+pub const BUS_NAME: &str = "org.freedesktop.secrets";
+
+const SERVICE_PATH: &str = "/org/freedesktop/secrets";
+const COLLECTION_PREFIX: &str = "/org/freedesktop/secrets/collection/";
+const ALIAS_PREFIX: &str = "/org/freedesktop/secrets/aliases/";
+const SESSION_PREFIX: &str = "/org/freedesktop/secrets/session/";
+
+const SERVICE_IFACE: &str = "org.freedesktop.Secret.Service";
+const COLLECTION_IFACE: &str = "org.freedesktop.Secret.Collection";
+const ITEM_IFACE: &str = "org.freedesktop.Secret.Item";
+const SESSION_IFACE: &str = "org.freedesktop.Secret.Session";
+const PROPERTIES_IFACE: &str = "org.freedesktop.DBus.Properties";
+const INTROSPECTABLE_IFACE: &str = "org.freedesktop.DBus.Introspectable";
+const PEER_IFACE: &str = "org.freedesktop.DBus.Peer";
+
+/// The collection that the `default` alias points to
+const DEFAULT_COLLECTION: &str = "login";
+
+/// The folder that collections other than the default one are stored in
+const COLLECTIONS_ROOT: &str = "secret-service";
+
+/// The secret struct from the Secret Service API: the session, the algorithm parameters, the
+/// secret and its content type
+type Secret = (OwnedObjectPath, Vec<u8>, Vec<u8>, String);
+
+/// A D-Bus error that is sent back to the caller
+#[derive(Debug)]
+struct Failure {
+    name: &'static str,
+    message: String,
+}
+
+impl Failure {
+    fn new(name: &'static str, message: impl Into<String>) -> Self {
+        Self {
+            name,
+            message: message.into(),
+        }
+    }
+
+    fn no_such_object(path: &str) -> Self {
+        Self::new(
+            "org.freedesktop.Secret.Error.NoSuchObject",
+            format!("no such object: {}", path),
+        )
+    }
+
+    fn unknown_method(header: &Header) -> Self {
+        Self::new(
+            "org.freedesktop.DBus.Error.UnknownMethod",
+            format!(
+                "unknown method {}.{}",
+                header
+                    .interface()
+                    .map(|iface| iface.as_str())
+                    .unwrap_or_default(),
+                header
+                    .member()
+                    .map(|member| member.as_str())
+                    .unwrap_or_default()
+            ),
+        )
+    }
+
+    fn invalid_args(message: impl Into<String>) -> Self {
+        Self::new("org.freedesktop.DBus.Error.InvalidArgs", message)
+    }
+}
+
+impl From<Error> for Failure {
+    fn from(err: Error) -> Self {
+        match err {
+            Error::AccessDenied(..) => {
+                Self::new("org.freedesktop.DBus.Error.AccessDenied", err.to_string())
+            }
+            Error::NotInStore(..) => {
+                Self::new("org.freedesktop.Secret.Error.NoSuchObject", err.to_string())
+            }
+            err => Self::new("org.freedesktop.DBus.Error.Failed", err.to_string()),
+        }
+    }
+}
+
+impl From<zbus::Error> for Failure {
+    fn from(err: zbus::Error) -> Self {
+        Failure::from(Error::from(err))
+    }
+}
+
+impl From<zbus::zvariant::Error> for Failure {
+    fn from(err: zbus::zvariant::Error) -> Self {
+        Failure::invalid_args(err.to_string())
+    }
+}
+
+type Reply<T> = std::result::Result<T, Failure>;
+
+/// The objects that the Secret Service exposes
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Object {
+    Service,
+    Collection(String),
+    Item { collection: String, path: PathBuf },
+    Session(String),
+}
+
+/// Turns the label of a new collection into the name of its folder in the store
+fn collection_name(label: &str) -> String {
+    let name: String = label
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+
+    if name.is_empty() {
+        "_".to_string()
+    } else {
+        name
+    }
+}
+
+/// The folder in the store that holds the items of a collection
+fn collection_root(collection: &str) -> PathBuf {
+    if collection == DEFAULT_COLLECTION {
+        PathBuf::from(crate::secret_tool::ROOT)
+    } else {
+        Path::new(COLLECTIONS_ROOT).join(collection)
+    }
+}
+
+/// Escapes a collection name for an object path. Letters and digits are kept, so `login` stays
+/// `login`, and every other byte becomes `_` and its hex value.
+fn collection_element(collection: &str) -> String {
+    collection
+        .bytes()
+        .map(|byte| {
+            if byte.is_ascii_alphanumeric() {
+                char::from(byte).to_string()
+            } else {
+                format!("_{:02x}", byte)
+            }
+        })
+        .collect()
+}
+
+/// The collection name that [`collection_element`] escaped, if `element` is one that it could
+/// have made
+fn parse_collection_element(element: &str) -> Option<String> {
+    let bytes = element.as_bytes();
+    let mut name = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'_' {
+            name.push(u8::from_str_radix(element.get(i + 1..i + 3)?, 16).ok()?);
+            i += 3;
+        } else {
+            name.push(bytes[i]);
+            i += 1;
+        }
+    }
+
+    String::from_utf8(name).ok()
+}
+
+/// Hex encodes an item's path inside of its collection
+fn item_element(relative: &Path) -> String {
+    let id: String = relative
+        .to_string_lossy()
+        .bytes()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+
+    format!("i{}", id)
+}
+
+/// The item path that [`item_element`] encoded
+fn parse_item_element(element: &str) -> Option<PathBuf> {
+    let hex = element.strip_prefix('i')?;
+    let bytes = (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect::<Option<Vec<u8>>>()?;
+
+    String::from_utf8(bytes).ok().map(PathBuf::from)
+}
+
+fn collection_object_path(collection: &str) -> Result<OwnedObjectPath> {
+    object_path(format!(
+        "{}{}",
+        COLLECTION_PREFIX,
+        collection_element(collection)
+    ))
+}
+
+fn item_object_path(collection: &str, path: &Path) -> Result<OwnedObjectPath> {
+    let relative = path
+        .strip_prefix(collection_root(collection))
+        .unwrap_or(path);
+
+    object_path(format!(
+        "{}{}/{}",
+        COLLECTION_PREFIX,
+        collection_element(collection),
+        item_element(relative)
+    ))
+}
+
+fn object_path(path: String) -> Result<OwnedObjectPath> {
+    Ok(OwnedObjectPath::try_from(path).map_err(zbus::Error::from)?)
+}
+
+/// The object path used to mean "no object", for example when no prompt is needed
+fn no_object() -> OwnedObjectPath {
+    ObjectPath::from_static_str_unchecked("/").into()
+}
+
+struct Daemon {
+    /// The store is kept up to date in `ctx.store`
+    ctx: Context,
+
+    /// The collections that exist, even if they have no items in them yet
+    collections: BTreeSet<String>,
+    aliases: BTreeMap<String, String>,
+    sessions: BTreeSet<String>,
+    next_session: u64,
+}
+
+impl Daemon {
+    fn new(ctx: &Context) -> Self {
+        let mut aliases = BTreeMap::new();
+        aliases.insert("default".to_string(), DEFAULT_COLLECTION.to_string());
+
+        Self {
+            ctx: ctx.clone(),
+            collections: BTreeSet::new(),
+            aliases,
+            sessions: BTreeSet::new(),
+            next_session: 0,
+        }
+    }
+
+    /// Picks up any changes that other programs (like the pass shim) have made to the store
+    fn reload(&mut self) -> Result<()> {
+        self.ctx.reload()
+    }
+
+    fn save(&mut self, store: Store) -> Result<()> {
+        self.ctx.save(&store)?;
+        self.ctx.store = store;
+        Ok(())
+    }
+
+    fn all_collections(&self) -> Result<BTreeSet<String>> {
+        let mut collections = self.collections.clone();
+        collections.insert(DEFAULT_COLLECTION.to_string());
+
+        for path in self.ctx.store.list_paths(Some(COLLECTIONS_ROOT))? {
+            if let Some(name) = path
+                .strip_prefix(COLLECTIONS_ROOT)
+                .ok()
+                .and_then(|path| path.iter().next())
+            {
+                collections.insert(name.to_string_lossy().into_owned());
+            }
+        }
+
+        Ok(collections)
+    }
+
+    /// Works out which object an object path refers to
+    fn resolve(&self, path: &str) -> Reply<Object> {
+        if path == SERVICE_PATH {
+            return Ok(Object::Service);
+        }
+
+        if let Some(id) = path.strip_prefix(SESSION_PREFIX) {
+            return if self.sessions.contains(id) {
+                Ok(Object::Session(id.to_string()))
+            } else {
+                Err(Failure::no_such_object(path))
+            };
+        }
+
+        let rest = if let Some(alias) = path.strip_prefix(ALIAS_PREFIX) {
+            let (alias, item) = alias.split_once('/').unwrap_or((alias, ""));
+            let collection = self
+                .aliases
+                .get(alias)
+                .ok_or_else(|| Failure::no_such_object(path))?;
+
+            if item.is_empty() {
+                collection.clone()
+            } else {
+                format!("{}/{}", collection, item)
+            }
+        } else if let Some(rest) = path.strip_prefix(COLLECTION_PREFIX) {
+            rest.to_string()
+        } else {
+            return Err(Failure::no_such_object(path));
+        };
+
+        let (collection, item) = rest.split_once('/').unwrap_or((&rest, ""));
+        let collections = self.all_collections()?;
+        let collection = parse_collection_element(collection)
+            .filter(|collection| collections.contains(collection))
+            .ok_or_else(|| Failure::no_such_object(path))?;
+
+        if item.is_empty() {
+            return Ok(Object::Collection(collection));
+        }
+
+        let relative = parse_item_element(item).ok_or_else(|| Failure::no_such_object(path))?;
+        let item_path = collection_root(&collection).join(relative);
+        if self.visible()?.get(&item_path)?.is_none() {
+            return Err(Failure::no_such_object(path));
+        }
+
+        Ok(Object::Item {
+            collection,
+            path: item_path,
+        })
+    }
+
+    fn visible(&self) -> Result<Store> {
+        self.ctx.policy.visible(&self.ctx.store)
+    }
+
+    /// The store paths of every visible item in `collection` that has all of `attributes`
+    fn search(
+        &self,
+        collection: &str,
+        attributes: &HashMap<String, String>,
+    ) -> Result<Vec<PathBuf>> {
+        let root = collection_root(collection);
+        let visible = self.visible()?;
+
+        let mut found = Vec::new();
+        for path in visible.list_paths(Some(&root))? {
+            let matches = path_attributes(&root, path)
+                .map(|item_attributes| {
+                    attributes
+                        .iter()
+                        .all(|(attribute, value)| item_attributes.get(attribute) == Some(value))
+                })
+                .unwrap_or(false);
+
+            if matches {
+                found.push(path.to_path_buf());
+            }
+        }
+
+        Ok(found)
+    }
+
+    fn item_paths(&self, collection: &str) -> Result<Vec<OwnedObjectPath>> {
+        self.search(collection, &HashMap::new())?
+            .iter()
+            .map(|path| item_object_path(collection, path))
+            .collect()
+    }
+
+    fn check_session(&self, session: &ObjectPath) -> Reply<()> {
+        match self.resolve(session.as_str()) {
+            Ok(Object::Session(_)) => Ok(()),
+            _ => Err(Failure::new(
+                "org.freedesktop.Secret.Error.NoSession",
+                format!("no such session: {}", session),
+            )),
+        }
+    }
+
+    fn get_secret(&self, path: &Path, session: OwnedObjectPath) -> Result<Secret> {
+        let value = audit::record(
+            "secret-service get",
+            Some(path),
+            self.ctx.policy.check(Operation::Show, path).and_then(|_| {
+                let value = self
+                    .ctx
+                    .store
+                    .get(path)?
+                    .map(str::to_string)
+                    .ok_or_else(|| Error::NotInStore(path.to_path_buf()))?;
+                self.ctx.record_reads([path]);
+                Ok(value)
+            }),
+        )?;
+
+        Ok((
+            session,
+            Vec::new(),
+            value.into_bytes(),
+            "text/plain".to_string(),
+        ))
+    }
+
+    fn set_secret(&mut self, path: &Path, secret: Secret) -> Result<()> {
+        let (_, _, value, _) = secret;
+
+        let result = self
+            .ctx
+            .policy
+            .check(Operation::Insert, path)
+            .and_then(|_| {
+                let mut store = self.ctx.store.clone();
+                *store.value_mut(path)? = crate::secret::Secret::from_bytes(value);
+                self.save(store)
+            });
+
+        audit::record("secret-service set", Some(path), result)
+    }
+
+    fn delete(&mut self, path: &Path) -> Result<()> {
+        let result = self
+            .ctx
+            .policy
+            .check_all(Operation::Rm, &self.ctx.store, path)
+            .and_then(|_| {
+                let mut store = self.ctx.store.clone();
+                store.remove(path)?;
+                self.save(store)
+            });
+
+        audit::record("secret-service delete", Some(path), result)
+    }
+
+    fn properties(&self, object: &Object, iface: &str) -> Reply<HashMap<String, OwnedValue>> {
+        let mut properties: HashMap<String, Value> = HashMap::new();
+
+        match (object, iface) {
+            (Object::Service, SERVICE_IFACE) => {
+                let collections = self
+                    .all_collections()?
+                    .iter()
+                    .map(|collection| collection_object_path(collection))
+                    .collect::<Result<Vec<_>>>()?;
+                properties.insert("Collections".to_string(), collections.into());
+            }
+            (Object::Collection(collection), COLLECTION_IFACE) => {
+                properties.insert("Items".to_string(), self.item_paths(collection)?.into());
+                properties.insert("Label".to_string(), collection.clone().into());
+                properties.insert("Locked".to_string(), false.into());
+                properties.insert("Created".to_string(), 0u64.into());
+                properties.insert("Modified".to_string(), 0u64.into());
+            }
+            (Object::Item { collection, path }, ITEM_IFACE) => {
+                let attributes: HashMap<String, String> =
+                    path_attributes(&collection_root(collection), path)
+                        .unwrap_or_default()
+                        .into_iter()
+                        .collect();
+
+                properties.insert("Locked".to_string(), false.into());
+                properties.insert("Attributes".to_string(), attributes.into());
+                properties.insert(
+                    "Label".to_string(),
+                    path.to_string_lossy().into_owned().into(),
+                );
+                properties.insert("Created".to_string(), 0u64.into());
+                properties.insert("Modified".to_string(), 0u64.into());
+            }
+            (Object::Session(_), SESSION_IFACE) => {}
+            _ => {
+                return Err(Failure::invalid_args(format!(
+                    "no such interface: {}",
+                    iface
+                )))
+            }
+        }
+
+        properties
+            .into_iter()
+            .map(|(name, value)| Ok((name, value.try_into()?)))
+            .collect()
+    }
+
+    fn set_property(&mut self, object: &Object, name: &str, value: OwnedValue) -> Reply<()> {
+        match (object, name) {
+            // Labels aren't kept, so there is nothing to change
+            (Object::Collection(_), "Label") | (Object::Item { .. }, "Label") => Ok(()),
+            (Object::Item { collection, path }, "Attributes") => {
+                // An item is stored by its attributes, so changing them moves the item
+                let attributes: BTreeMap<String, String> =
+                    HashMap::<String, String>::try_from(value)?
+                        .into_iter()
+                        .collect();
+                let new_path = attributes_path(&collection_root(collection), &attributes);
+
+                let value = self
+                    .ctx
+                    .store
+                    .get(path)?
+                    .map(str::to_string)
+                    .unwrap_or_default();
+                self.delete(path)?;
+                self.set_secret(
+                    &new_path,
+                    (no_object(), Vec::new(), value.into_bytes(), String::new()),
+                )?;
+
+                Ok(())
+            }
+            _ => Err(Failure::invalid_args(format!(
+                "property {} can't be changed",
+                name
+            ))),
+        }
+    }
+
+    fn introspect(&self, object: &Object) -> Reply<String> {
+        let mut children = Vec::new();
+        let ifaces: &[&str] = match object {
+            Object::Service => {
+                children.push("collection".to_string());
+                &[SERVICE_IFACE]
+            }
+            Object::Collection(collection) => {
+                for path in self.search(collection, &HashMap::new())? {
+                    let item = item_object_path(collection, &path)?;
+                    children.extend(item.as_str().rsplit('/').next().map(str::to_string));
+                }
+                &[COLLECTION_IFACE]
+            }
+            Object::Item { .. } => &[ITEM_IFACE],
+            Object::Session(_) => &[SESSION_IFACE],
+        };
+
+        let mut xml = String::from("<node>\n");
+        for iface in ifaces
+            .iter()
+            .chain(&[PROPERTIES_IFACE, INTROSPECTABLE_IFACE, PEER_IFACE])
+        {
+            xml.push_str(&format!("  <interface name=\"{}\"/>\n", iface));
+        }
+        for child in children {
+            xml.push_str(&format!("  <node name=\"{}\"/>\n", child));
+        }
+        xml.push_str("</node>\n");
+
+        Ok(xml)
+    }
+
+    /// The object path of the collection a path or alias refers to, for the introspection of
+    /// `/org/freedesktop/secrets/collection`
+    fn collection_node(&self, path: &str) -> Reply<Option<String>> {
+        if path != COLLECTION_PREFIX.trim_end_matches('/') {
+            return Ok(None);
+        }
+
+        let mut xml = String::from("<node>\n");
+        for collection in self.all_collections()? {
+            xml.push_str(&format!(
+                "  <node name=\"{}\"/>\n",
+                collection_element(&collection)
+            ));
+        }
+        xml.push_str("</node>\n");
+
+        Ok(Some(xml))
+    }
+
+    /// Handles a single method call, sending back its reply
+    fn handle(&mut self, conn: &Connection, msg: &Message) -> Reply<()> {
+        let header = msg.header();
+        let body = msg.body();
+
+        let path = header.path().map(|path| path.as_str()).unwrap_or_default();
+        let iface = header
+            .interface()
+            .map(|iface| iface.as_str())
+            .unwrap_or_default();
+        let member = header
+            .member()
+            .map(|member| member.as_str())
+            .unwrap_or_default();
+
+        if iface == PEER_IFACE && member == "Ping" {
+            return Ok(conn.reply(&header, &())?);
+        }
+        if iface == INTROSPECTABLE_IFACE && member == "Introspect" {
+            if let Some(xml) = self.collection_node(path)? {
+                return Ok(conn.reply(&header, &xml)?);
+            }
+        }
+
+        self.reload()?;
+        let object = self.resolve(path)?;
+
+        match (&object, iface, member) {
+            (_, INTROSPECTABLE_IFACE, "Introspect") => {
+                conn.reply(&header, &self.introspect(&object)?)?;
+            }
+            (_, PROPERTIES_IFACE, "GetAll") => {
+                let iface: String = body.deserialize()?;
+                conn.reply(&header, &self.properties(&object, &iface)?)?;
+            }
+            (_, PROPERTIES_IFACE, "Get") => {
+                let (iface, name): (String, String) = body.deserialize()?;
+                let value = self
+                    .properties(&object, &iface)?
+                    .remove(&name)
+                    .ok_or_else(|| Failure::invalid_args(format!("no such property: {}", name)))?;
+                conn.reply(&header, &value)?;
+            }
+            (_, PROPERTIES_IFACE, "Set") => {
+                let (_, name, value): (String, String, OwnedValue) = body.deserialize()?;
+                self.set_property(&object, &name, value)?;
+                conn.reply(&header, &())?;
+            }
+
+            (Object::Service, SERVICE_IFACE, "OpenSession") => {
+                let (algorithm, _): (String, OwnedValue) = body.deserialize()?;
+                if algorithm != "plain" {
+                    return Err(Failure::new(
+                        "org.freedesktop.DBus.Error.NotSupported",
+                        format!("session algorithm {} is not supported", algorithm),
+                    ));
+                }
+
+                let id = self.next_session.to_string();
+                self.next_session += 1;
+                self.sessions.insert(id.clone());
+
+                conn.reply(
+                    &header,
+                    &(
+                        Value::from(""),
+                        object_path(format!("{}{}", SESSION_PREFIX, id))?,
+                    ),
+                )?;
+            }
+            (Object::Service, SERVICE_IFACE, "CreateCollection") => {
+                let (properties, alias): (HashMap<String, OwnedValue>, String) =
+                    body.deserialize()?;
+                let label = properties
+                    .get("org.freedesktop.Secret.Collection.Label")
+                    .and_then(|label| String::try_from(label.try_clone().ok()?).ok())
+                    .unwrap_or_default();
+
+                let collection = match self.aliases.get(&alias) {
+                    Some(collection) if !alias.is_empty() => collection.clone(),
+                    _ => collection_name(&label),
+                };
+                self.collections.insert(collection.clone());
+                if !alias.is_empty() {
+                    self.aliases.insert(alias, collection.clone());
+                }
+
+                conn.reply(
+                    &header,
+                    &(collection_object_path(&collection)?, no_object()),
+                )?;
+            }
+            (Object::Service, SERVICE_IFACE, "SearchItems") => {
+                let attributes: HashMap<String, String> = body.deserialize()?;
+
+                let mut unlocked = Vec::new();
+                for collection in self.all_collections()? {
+                    for path in self.search(&collection, &attributes)? {
+                        unlocked.push(item_object_path(&collection, &path)?);
+                    }
+                }
+
+                conn.reply(&header, &(unlocked, Vec::<OwnedObjectPath>::new()))?;
+            }
+            (Object::Service, SERVICE_IFACE, "Unlock") => {
+                // Nothing is ever locked
+                let objects: Vec<OwnedObjectPath> = body.deserialize()?;
+                conn.reply(&header, &(objects, no_object()))?;
+            }
+            (Object::Service, SERVICE_IFACE, "Lock") => {
+                let _: Vec<OwnedObjectPath> = body.deserialize()?;
+                conn.reply(&header, &(Vec::<OwnedObjectPath>::new(), no_object()))?;
+            }
+            (Object::Service, SERVICE_IFACE, "GetSecrets") => {
+                let (items, session): (Vec<OwnedObjectPath>, OwnedObjectPath) =
+                    body.deserialize()?;
+                self.check_session(&session)?;
+
+                let mut secrets = HashMap::new();
+                for item in items {
+                    if let Ok(Object::Item { path, .. }) = self.resolve(item.as_str()) {
+                        secrets.insert(item, self.get_secret(&path, session.clone())?);
+                    }
+                }
+
+                conn.reply(&header, &secrets)?;
+            }
+            (Object::Service, SERVICE_IFACE, "ReadAlias") => {
+                let name: String = body.deserialize()?;
+                let collection = match self.aliases.get(&name) {
+                    Some(collection) => collection_object_path(collection)?,
+                    None => no_object(),
+                };
+
+                conn.reply(&header, &collection)?;
+            }
+            (Object::Service, SERVICE_IFACE, "SetAlias") => {
+                let (name, collection): (String, OwnedObjectPath) = body.deserialize()?;
+                match self.resolve(collection.as_str()) {
+                    Ok(Object::Collection(collection)) => {
+                        self.aliases.insert(name, collection);
+                    }
+                    _ if collection.as_str() == "/" => {
+                        self.aliases.remove(&name);
+                    }
+                    _ => return Err(Failure::no_such_object(collection.as_str())),
+                }
+
+                conn.reply(&header, &())?;
+            }
+
+            (Object::Collection(collection), COLLECTION_IFACE, "SearchItems") => {
+                let attributes: HashMap<String, String> = body.deserialize()?;
+                let found = self
+                    .search(collection, &attributes)?
+                    .iter()
+                    .map(|path| item_object_path(collection, path))
+                    .collect::<Result<Vec<_>>>()?;
+
+                conn.reply(&header, &found)?;
+            }
+            (Object::Collection(collection), COLLECTION_IFACE, "CreateItem") => {
+                let (properties, secret, _replace): (HashMap<String, OwnedValue>, Secret, bool) =
+                    body.deserialize()?;
+                self.check_session(&secret.0)?;
+
+                let attributes: BTreeMap<String, String> = properties
+                    .get("org.freedesktop.Secret.Item.Attributes")
+                    .map(|attributes| -> Reply<HashMap<String, String>> {
+                        Ok(HashMap::try_from(attributes.try_clone()?)?)
+                    })
+                    .transpose()?
+                    .unwrap_or_default()
+                    .into_iter()
+                    .collect();
+
+                // Items are stored by their attributes, so an item with the same attributes is
+                // always replaced
+                let path = attributes_path(&collection_root(collection), &attributes);
+                self.set_secret(&path, secret)?;
+
+                conn.reply(
+                    &header,
+                    &(item_object_path(collection, &path)?, no_object()),
+                )?;
+            }
+            (Object::Collection(collection), COLLECTION_IFACE, "Delete") => {
+                let collection = collection.clone();
+                self.delete(&collection_root(&collection))?;
+                self.collections.remove(&collection);
+
+                conn.reply(&header, &no_object())?;
+            }
+
+            (Object::Item { path, .. }, ITEM_IFACE, "GetSecret") => {
+                let session: OwnedObjectPath = body.deserialize()?;
+                self.check_session(&session)?;
+
+                conn.reply(&header, &self.get_secret(path, session)?)?;
+            }
+            (Object::Item { path, .. }, ITEM_IFACE, "SetSecret") => {
+                let secret: Secret = body.deserialize()?;
+                self.check_session(&secret.0)?;
+                self.set_secret(path, secret)?;
+
+                conn.reply(&header, &())?;
+            }
+            (Object::Item { path, .. }, ITEM_IFACE, "Delete") => {
+                self.delete(path)?;
+
+                conn.reply(&header, &no_object())?;
+            }
+
+            (Object::Session(id), SESSION_IFACE, "Close") => {
+                self.sessions.remove(id);
+
+                conn.reply(&header, &())?;
+            }
+
+            _ => return Err(Failure::unknown_method(&header)),
+        }
+
+        Ok(())
+    }
+}
+
+/// Serves the Secret Service on the session bus until the bus goes away
+pub fn serve(ctx: &Context) -> Result<()> {
+    let conn = Connection::session()?;
+    conn.request_name(BUS_NAME)?;
+
+    if !ctx.quiet {
+        eprintln!("Serving {} on the session bus", BUS_NAME);
+    }
+
+    let mut daemon = Daemon::new(ctx);
+
+    for msg in zbus::blocking::MessageIterator::from(&conn) {
+        let msg = msg?;
+        if msg.message_type() != Type::MethodCall {
+            continue;
+        }
+
+        if let Err(failure) = daemon.handle(&conn, &msg) {
+            conn.reply_error(&msg.header(), failure.name, &failure.message)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// A private session bus with the Secret Service running on it.
+///
+/// Both the bus and the service are stopped when this is dropped.
+pub struct PrivateBus {
+    /// The address to connect to the bus, for DBUS_SESSION_BUS_ADDRESS
+    pub address: String,
+    dbus_daemon: Child,
+    service: Option<Child>,
+}
+
+impl PrivateBus {
+    /// Starts dbus-daemon with a minimal config written to `config_path`, and then runs
+    /// `service` on it.
+    ///
+    /// `service` should run `imposter-pass secret-service`. This waits until the service has
+    /// claimed its name on the bus.
+    pub fn start(config_path: &Path, mut service: Command) -> Result<Self> {
+        use std::io::BufRead;
+
+        let socket_dir = config_path.parent().unwrap_or_else(|| Path::new("/tmp"));
+        std::fs::write(
+            config_path,
+            format!(
+                r#"<!DOCTYPE busconfig PUBLIC "-//freedesktop//DTD D-Bus Bus Configuration 1.0//EN"
+ "http://www.freedesktop.org/standards/dbus/1.0/busconfig.dtd">
+<busconfig>
+  <type>session</type>
+  <listen>unix:dir={dir}</listen>
+  <auth>EXTERNAL</auth>
+  <policy context="default">
+    <allow send_destination="*"/>
+    <allow receive_sender="*"/>
+    <allow own="*"/>
+  </policy>
+</busconfig>
+"#,
+                dir = socket_dir.display()
+            ),
+        )?;
+
+        let mut dbus_daemon = Command::new("dbus-daemon")
+            .arg("--nofork")
+            .arg("--print-address=1")
+            .arg(format!("--config-file={}", config_path.display()))
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .spawn()?;
+
+        let mut address = String::new();
+        std::io::BufReader::new(dbus_daemon.stdout.take().expect("stdout is piped"))
+            .read_line(&mut address)?;
+
+        let mut bus = Self {
+            address: address.trim().to_string(),
+            dbus_daemon,
+            service: None,
+        };
+
+        bus.service = Some(
+            service
+                .env("DBUS_SESSION_BUS_ADDRESS", &bus.address)
+                .stdin(Stdio::null())
+                .spawn()?,
+        );
+
+        let conn = zbus::blocking::connection::Builder::address(bus.address.as_str())?.build()?;
+        let dbus = zbus::blocking::fdo::DBusProxy::new(&conn)?;
+        let name = zbus::names::BusName::try_from(BUS_NAME).map_err(zbus::Error::from)?;
+        for _ in 0..100 {
+            if dbus
+                .name_has_owner(name.clone())
+                .map_err(zbus::Error::from)?
+            {
+                return Ok(bus);
+            }
+
+            if let Some(status) = bus.service.as_mut().and_then(|s| s.try_wait().transpose()) {
+                return Err(Error::ProgramFailed(
+                    "imposter-pass secret-service".into(),
+                    status?,
+                ));
+            }
+
+            std::thread::sleep(std::time::Duration::from_millis(50));
+        }
+
+        Err(Error::Usage(format!(
+            "timed out waiting for {} to start on {}",
+            BUS_NAME, bus.address
+        )))
+    }
+}
+
+impl Drop for PrivateBus {
+    fn drop(&mut self) {
+        for child in self.service.iter_mut().chain(Some(&mut self.dbus_daemon)) {
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::policy::Policy;
+
+    /// A daemon serving a store with an entry at each of `paths`, under `policy`
+    fn daemon(paths: &[PathBuf], policy: &str) -> Daemon {
+        let mut store = Store::default();
+        for path in paths {
+            *store.value_mut(path).unwrap() = "secret".to_string().into();
+        }
+
+        Daemon::new(&Context {
+            quiet: true,
+            show_secrets: false,
+            store,
+            layers: Vec::new(),
+            socket: None,
+            policy: serde_json::from_str::<Policy>(policy).unwrap(),
+            policy_path: None,
+            writer: None,
+            track_reads: false,
+        })
+    }
+
+    fn item(collection: &str, pairs: &[(&str, &str)]) -> PathBuf {
+        let attributes = pairs
+            .iter()
+            .map(|(attribute, value)| (attribute.to_string(), value.to_string()))
+            .collect();
+        attributes_path(&collection_root(collection), &attributes)
+    }
+
+    const COLLECTIONS: [&str; 7] = ["login", "my-app", "a_b", "a.b", "x y", "päss", "_2d"];
+
+    #[test]
+    fn collection_object_paths_round_trip() {
+        let paths: Vec<PathBuf> = COLLECTIONS
+            .iter()
+            .map(|collection| item(collection, &[("service", "mail")]))
+            .collect();
+        let daemon = daemon(&paths, "{}");
+
+        assert_eq!(
+            collection_object_path("login").unwrap().as_str(),
+            "/org/freedesktop/secrets/collection/login"
+        );
+        for collection in &COLLECTIONS {
+            let path = collection_object_path(collection).unwrap();
+            assert_eq!(
+                daemon.resolve(path.as_str()).unwrap(),
+                Object::Collection(collection.to_string()),
+                "{}",
+                path
+            );
+        }
+
+        // Every collection can be listed, whatever its name
+        assert!(daemon.properties(&Object::Service, SERVICE_IFACE).is_ok());
+    }
+
+    #[test]
+    fn item_object_paths_round_trip() {
+        let paths = vec![
+            item("login", &[("service", "mail"), ("user", "me@example.com")]),
+            item("login", &[("dots", ".."), ("empty", "")]),
+            item("my-app", &[("unicode", "päss/wörd")]),
+        ];
+        let daemon = daemon(&paths, "{}");
+
+        for (collection, path) in ["login", "login", "my-app"].iter().zip(&paths) {
+            let object_path = item_object_path(collection, path).unwrap();
+            assert_eq!(
+                daemon.resolve(object_path.as_str()).unwrap(),
+                Object::Item {
+                    collection: collection.to_string(),
+                    path: path.clone(),
+                },
+                "{}",
+                object_path
+            );
+        }
+    }
+
+    #[test]
+    fn resolve_finds_only_what_exists() {
+        let shown = item("login", &[("service", "mail")]);
+        let hidden = item("login", &[("service", "bank")]);
+        let mut daemon = daemon(
+            &[shown.clone(), hidden.clone()],
+            r#"{"rules": [{"prefix": "secret-tool/service/bank", "deny": ["show"]}]}"#,
+        );
+        daemon.sessions.insert("0".to_string());
+
+        assert_eq!(daemon.resolve(SERVICE_PATH).unwrap(), Object::Service);
+        assert_eq!(
+            daemon
+                .resolve("/org/freedesktop/secrets/session/0")
+                .unwrap(),
+            Object::Session("0".to_string())
+        );
+        assert_eq!(
+            daemon
+                .resolve("/org/freedesktop/secrets/aliases/default")
+                .unwrap(),
+            Object::Collection("login".to_string())
+        );
+        let alias_item = format!(
+            "/org/freedesktop/secrets/aliases/default/{}",
+            item_element(shown.strip_prefix(collection_root("login")).unwrap())
+        );
+        assert_eq!(
+            daemon.resolve(&alias_item).unwrap(),
+            Object::Item {
+                collection: "login".to_string(),
+                path: shown,
+            }
+        );
+
+        for path in &[
+            item_object_path("login", &hidden).unwrap().as_str(),
+            item_object_path("login", &item("login", &[("service", "none")]))
+                .unwrap()
+                .as_str(),
+            "/org/freedesktop/secrets/collection/nothing",
+            "/org/freedesktop/secrets/collection/login_",
+            "/org/freedesktop/secrets/collection/login_zz",
+            "/org/freedesktop/secrets/collection/login/iz",
+            "/org/freedesktop/secrets/collection/login/x",
+            "/org/freedesktop/secrets/session/1",
+            "/org/freedesktop/secrets/aliases/nothing",
+            "/elsewhere",
+        ] {
+            assert!(daemon.resolve(path).is_err(), "{}", path);
+        }
+    }
+}
@@ -0,0 +1,291 @@
+//! Translates the secret-tool (libsecret) command line onto the store.
+//!
+//! secret-tool finds secrets by a set of attributes rather than by a path. Each secret is stored
+//! at `secret-tool/<attribute>/<value>/...`, with the attributes sorted by name. Any `/` or `%`
+//! in an attribute or value is percent encoded, so that it can't change the shape of the path.
+
+use crate::cli::StoreOpts;
+use crate::context::Context;
+use crate::error::{Error, Result};
+use crate::policy::Operation;
+use crate::secret::Secret;
+use std::collections::BTreeMap;
+use std::io::{IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use structopt::StructOpt;
+
+/// The folder that secret-tool secrets are stored in
+pub const ROOT: &str = "secret-tool";
+
+#[derive(Debug, StructOpt)]
+pub enum SecretToolCmd {
+    /// Prints the first secret that has all of the given attributes
+    Lookup {
+        #[structopt(name = "attribute value", required = true)]
+        attributes: Vec<String>,
+    },
+
+    /// Stores a secret read from stdin under the given attributes
+    Store {
+        /// The label of the secret. This is accepted for compatibility, but not kept.
+        #[structopt(long)]
+        label: String,
+
+        /// The collection to store the secret in. This is accepted for compatibility, but
+        /// every secret goes into the same store.
+        #[structopt(long = "collection")]
+        _collection: Option<String>,
+
+        #[structopt(name = "attribute value", required = true)]
+        attributes: Vec<String>,
+    },
+
+    /// Removes every secret that has all of the given attributes
+    Clear {
+        #[structopt(name = "attribute value", required = true)]
+        attributes: Vec<String>,
+    },
+}
+
+#[derive(Debug, StructOpt)]
+#[structopt(name = "secret-tool")]
+pub struct SecretTool {
+    #[structopt(flatten)]
+    pub opts: StoreOpts,
+
+    #[structopt(subcommand)]
+    pub cmd: SecretToolCmd,
+}
+
+impl SecretToolCmd {
+    /// The name of the subcommand, for the audit log
+    pub fn name(&self) -> &'static str {
+        match self {
+            SecretToolCmd::Lookup { .. } => "secret-tool lookup",
+            SecretToolCmd::Store { .. } => "secret-tool store",
+            SecretToolCmd::Clear { .. } => "secret-tool clear",
+        }
+    }
+
+    fn attributes(&self) -> &[String] {
+        match self {
+            SecretToolCmd::Lookup { attributes }
+            | SecretToolCmd::Store { attributes, .. }
+            | SecretToolCmd::Clear { attributes } => attributes,
+        }
+    }
+
+    /// The store path that the attributes map onto, if they are valid
+    pub fn pass_name(&self) -> Option<PathBuf> {
+        parse_attributes(self.attributes())
+            .map(|attributes| attributes_path(Path::new(ROOT), &attributes))
+            .ok()
+    }
+}
+
+/// Turns `attribute value attribute value ...` into a map
+fn parse_attributes(args: &[String]) -> Result<BTreeMap<String, String>> {
+    if !args.len().is_multiple_of(2) {
+        return Err(Error::Usage(
+            "must specify attribute and value pairs".to_string(),
+        ));
+    }
+
+    Ok(args
+        .chunks(2)
+        .map(|pair| (pair[0].clone(), pair[1].clone()))
+        .collect())
+}
+
+/// Turns an attribute or value into a single path component that the store keeps as it is.
+///
+/// `%` and `/` are percent-encoded, and so is a leading `.`, which would otherwise make `.` and
+/// `..` special and hide the entry as metadata. An empty string is a lone `%`, which nothing
+/// else encodes to.
+fn encode(text: &str) -> String {
+    if text.is_empty() {
+        return "%".to_string();
+    }
+
+    let encoded = text.replace('%', "%25").replace('/', "%2F");
+    match encoded.strip_prefix('.') {
+        Some(rest) => format!("%2E{}", rest),
+        None => encoded,
+    }
+}
+
+fn decode(text: &str) -> String {
+    if text == "%" {
+        return String::new();
+    }
+
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = if bytes[i] == b'%' {
+            text.get(i + 1..i + 3)
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+        } else {
+            None
+        };
+
+        match escaped {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// The store path for a set of attributes, inside of the folder `root`
+pub fn attributes_path(root: &Path, attributes: &BTreeMap<String, String>) -> PathBuf {
+    let mut path = root.to_path_buf();
+    for (attribute, value) in attributes {
+        path.push(encode(attribute));
+        path.push(encode(value));
+    }
+
+    path
+}
+
+/// The attributes of a secret stored at `path`, if it is inside of the folder `root`
+pub fn path_attributes(root: &Path, path: &Path) -> Option<BTreeMap<String, String>> {
+    let mut components = path.strip_prefix(root).ok()?.iter();
+    let mut attributes = BTreeMap::new();
+
+    while let Some(attribute) = components.next() {
+        let value = components.next()?;
+        attributes.insert(decode(attribute.to_str()?), decode(value.to_str()?));
+    }
+
+    Some(attributes)
+}
+
+/// Every visible secret that has all of `attributes`
+fn matching(ctx: &Context, attributes: &BTreeMap<String, String>) -> Result<Vec<PathBuf>> {
+    Ok(ctx
+        .policy
+        .visible(&ctx.store)?
+        .list_paths(Some(ROOT))?
+        .filter(|path| {
+            path_attributes(Path::new(ROOT), path)
+                .map(|found| {
+                    attributes
+                        .iter()
+                        .all(|(attribute, value)| found.get(attribute) == Some(value))
+                })
+                .unwrap_or(false)
+        })
+        .map(Path::to_path_buf)
+        .collect())
+}
+
+pub fn run(ctx: &Context, cmd: &SecretToolCmd) -> Result<()> {
+    let attributes = parse_attributes(cmd.attributes())?;
+
+    match cmd {
+        SecretToolCmd::Lookup { .. } => {
+            let path = matching(ctx, &attributes)?
+                .into_iter()
+                .next()
+                .ok_or_else(|| Error::NotInStore(attributes_path(Path::new(ROOT), &attributes)))?;
+            let value = ctx.store.get(&path)?.unwrap_or_default();
+
+            // Like secret-tool, only add a newline when a person is going to read it
+            print!("{}", value);
+            if std::io::stdout().is_terminal() {
+                println!();
+            }
+            std::io::stdout().flush()?;
+
+            ctx.record_reads([path.as_path()]);
+            Ok(())
+        }
+        SecretToolCmd::Store { label, .. } => {
+            let path = attributes_path(Path::new(ROOT), &attributes);
+            ctx.policy.check(Operation::Insert, &path)?;
+
+            let secret = if std::io::stdin().is_terminal() {
+                Secret::from(rpassword::read_password_from_tty(Some(&format!(
+                    "Password for {}: ",
+                    label
+                )))?)
+            } else {
+                Secret::read_to_end(&mut std::io::stdin())?
+            };
+
+            let mut store = ctx.store.clone();
+            *store.value_mut(&path)? = secret;
+
+            ctx.save(&store)
+        }
+        SecretToolCmd::Clear { .. } => {
+            let paths = matching(ctx, &attributes)?;
+            for path in &paths {
+                ctx.policy.check(Operation::Rm, path)?;
+            }
+
+            let mut store = ctx.store.clone();
+            for path in &paths {
+                store.remove(path)?;
+            }
+
+            ctx.save(&store)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::Store;
+
+    fn round_trip(pairs: &[(&str, &str)]) {
+        let attributes: BTreeMap<String, String> = pairs
+            .iter()
+            .map(|(attribute, value)| (attribute.to_string(), value.to_string()))
+            .collect();
+        let path = attributes_path(Path::new(ROOT), &attributes);
+
+        // The path has to be kept exactly as it is, and not be hidden as metadata
+        let mut store = Store::default();
+        *store.value_mut(&path).unwrap() = "secret".to_string().into();
+        let (stored, _) = store
+            .list(Some(ROOT))
+            .unwrap()
+            .next()
+            .expect("entry is hidden");
+        assert_eq!(stored, path, "{:?}", pairs);
+
+        assert_eq!(
+            path_attributes(Path::new(ROOT), stored),
+            Some(attributes),
+            "{:?}",
+            pairs
+        );
+    }
+
+    #[test]
+    fn attributes_round_trip() {
+        round_trip(&[("service", "mail"), ("user", "me@example.com")]);
+        round_trip(&[("url", "https://example.com/a%20b"), ("percent", "%")]);
+        round_trip(&[("dot", "."), ("dots", "..")]);
+        round_trip(&[(".hidden", ".profile"), ("x", "a.b")]);
+        round_trip(&[("empty", ""), ("", "empty attribute")]);
+        round_trip(&[("literal", "%2E%2F%"), ("unicode", "päss/wörd")]);
+    }
+
+    #[test]
+    fn old_paths_still_decode() {
+        assert_eq!(decode("a%2Fb%25c"), "a/b%c");
+        assert_eq!(decode("plain"), "plain");
+    }
+}
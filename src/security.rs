@@ -0,0 +1,298 @@
+//! Checks, before a store is loaded, whether other users or processes could read or change it,
+//! and suggests something safer if they could.
+
+use crate::error::{Error, Result};
+use crate::integrity;
+use crate::seal;
+use crate::source::Source;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// What to do about the problems that are found
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    /// Don't check anything
+    Off,
+    /// Print a warning for each problem, unless in quiet mode
+    Warn,
+    /// Refuse to use the store if there are any problems
+    Strict,
+}
+
+impl Mode {
+    pub const NAMES: [&'static str; 3] = ["off", "warn", "strict"];
+}
+
+impl FromStr for Mode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "off" => Ok(Mode::Off),
+            "warn" => Ok(Mode::Warn),
+            "strict" => Ok(Mode::Strict),
+            _ => Err(format!("{:?} is not a security mode", s)),
+        }
+    }
+}
+
+/// Something that could expose the store, and what to do about it
+struct Problem {
+    problem: String,
+    suggestion: String,
+}
+
+/// Directories that every user can create files in
+const SHARED_DIRS: [&str; 3] = ["/tmp", "/var/tmp", "/dev/shm"];
+
+/// Checks the sources of the store. `given_inline` says whether inline stores came from the
+/// command line or the environment, rather than the config file.
+pub fn check(mode: Mode, quiet: bool, sources: &[Source], given_inline: bool) -> Result<()> {
+    if mode == Mode::Off {
+        return Ok(());
+    }
+
+    let mut problems = Vec::new();
+    for source in sources {
+        match source {
+            Source::Inline(_) if given_inline => problems.push(Problem {
+                problem: "the store was given inline, where other processes can read it in /proc/<pid>/environ, ps or docker inspect".to_string(),
+                suggestion: "Give --store a file instead, or a directory of secret files like /run/secrets".to_string(),
+            }),
+            Source::Inline(_) | Source::Sealed(_) => {}
+            Source::File(path) | Source::Directory(path) => check_path(path, &mut problems)?,
+        }
+    }
+    let secret_vars = [
+        ("MAC key", integrity::KEY_VAR, integrity::KEY_FILE_VAR),
+        (
+            "passphrase",
+            seal::PASSPHRASE_VAR,
+            seal::PASSPHRASE_FILE_VAR,
+        ),
+    ];
+    for (secret, var, file_var) in secret_vars {
+        if std::env::var_os(var).is_some() {
+            problems.push(Problem {
+                problem: format!(
+                    "the {} was given in {}, where other processes can read it",
+                    secret, var
+                ),
+                suggestion: format!(
+                    "Put it in a file only you can read, and set {} to its path",
+                    file_var
+                ),
+            });
+        }
+    }
+
+    match mode {
+        Mode::Strict if !problems.is_empty() => Err(Error::Insecure(
+            problems
+                .iter()
+                .map(|problem| format!("{}. {}.", problem.problem, problem.suggestion))
+                .collect::<Vec<_>>()
+                .join("\n"),
+        )),
+        _ => {
+            if !quiet {
+                for problem in &problems {
+                    eprintln!("Warning: {}. {}.", problem.problem, problem.suggestion);
+                }
+            }
+            Ok(())
+        }
+    }
+}
+
+fn check_path(path: &Path, problems: &mut Vec<Problem>) -> Result<()> {
+    let absolute = if path.is_absolute() {
+        path.to_path_buf()
+    } else {
+        std::env::current_dir()?.join(path)
+    };
+
+    let temp_dir = std::env::temp_dir();
+    let shared = SHARED_DIRS
+        .iter()
+        .map(PathBuf::from)
+        .chain(std::iter::once(temp_dir))
+        .any(|dir| absolute.starts_with(dir));
+    if shared {
+        problems.push(Problem {
+            problem: format!("{:?} is in a shared temporary directory", path),
+            suggestion: "Keep the store somewhere only you can reach, like $XDG_RUNTIME_DIR or your home directory".to_string(),
+        });
+    }
+
+    #[cfg(target_family = "unix")]
+    {
+        if let Ok(metadata) = std::fs::metadata(path) {
+            if metadata.is_file() {
+                check_file(path, &metadata, problems);
+            } else if metadata.is_dir() {
+                check_dir_files(path, &mut Vec::new(), problems)?;
+            }
+        }
+
+        // Anyone who can write to any directory on the way to the store can replace the store,
+        // or a directory it is in, unless the sticky bit stops them. Links are followed, since
+        // it is the directories the store is really in that matter.
+        let real = std::fs::canonicalize(&absolute)
+            .ok()
+            .or_else(|| {
+                let parent = std::fs::canonicalize(absolute.parent()?).ok()?;
+                Some(parent.join(absolute.file_name()?))
+            })
+            .unwrap_or(absolute);
+        let skip = if path.is_dir() { 0 } else { 1 };
+        for dir in real.ancestors().skip(skip) {
+            check_dir(dir, problems);
+        }
+    }
+
+    Ok(())
+}
+
+/// Checks that only its owner can read or change the store file at `path`
+#[cfg(target_family = "unix")]
+fn check_file(path: &Path, metadata: &std::fs::Metadata, problems: &mut Vec<Problem>) {
+    use std::os::unix::fs::PermissionsExt;
+
+    let mode = metadata.permissions().mode();
+    if mode & 0o066 != 0 {
+        problems.push(Problem {
+            problem: format!(
+                "{:?} can be read or changed by other users (its mode is {:o})",
+                path,
+                mode & 0o777
+            ),
+            suggestion: format!("Run chmod 600 {:?}", path),
+        });
+    }
+}
+
+/// Checks that other users can't add, remove or replace files in `dir`
+#[cfg(target_family = "unix")]
+fn check_dir(dir: &Path, problems: &mut Vec<Problem>) {
+    use std::os::unix::fs::PermissionsExt;
+
+    if let Ok(metadata) = std::fs::metadata(dir) {
+        let mode = metadata.permissions().mode();
+        if mode & 0o022 != 0 && mode & 0o1000 == 0 {
+            problems.push(Problem {
+                problem: format!(
+                    "{:?} can be written by other users, who could replace the store",
+                    dir
+                ),
+                suggestion: format!("Run chmod go-w {:?}", dir),
+            });
+        }
+    }
+}
+
+/// Checks every file and folder in a directory store, the same way that they are read: hidden
+/// names are skipped, links are followed, and directories that link back to a parent are only
+/// checked once
+#[cfg(target_family = "unix")]
+fn check_dir_files(
+    dir: &Path,
+    parents: &mut Vec<PathBuf>,
+    problems: &mut Vec<Problem>,
+) -> Result<()> {
+    let real_dir = std::fs::canonicalize(dir)?;
+    if parents.contains(&real_dir) {
+        return Ok(());
+    }
+    parents.push(real_dir);
+
+    for dir_entry in std::fs::read_dir(dir)? {
+        let dir_entry = dir_entry?;
+        if dir_entry.file_name().to_string_lossy().starts_with('.') {
+            continue;
+        }
+
+        let path = dir_entry.path();
+        let metadata = match std::fs::metadata(&path) {
+            Ok(metadata) => metadata,
+            Err(_) => continue,
+        };
+        if metadata.is_dir() {
+            check_dir(&path, problems);
+            check_dir_files(&path, parents, problems)?;
+        } else if metadata.is_file() {
+            check_file(&path, &metadata, problems);
+        }
+    }
+
+    parents.pop();
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+
+    /// A fresh directory for one test
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "imposter-pass-security-{}-{}",
+            name,
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn chmod(path: &Path, mode: u32) {
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode)).unwrap();
+    }
+
+    /// The problems found with `path`, other than it being in a temporary directory
+    fn problems(path: &Path) -> Vec<String> {
+        let mut problems = Vec::new();
+        check_path(path, &mut problems).unwrap();
+        problems
+            .into_iter()
+            .map(|problem| problem.problem)
+            .filter(|problem| !problem.contains("temporary directory"))
+            .collect()
+    }
+
+    #[test]
+    fn every_parent_directory_is_checked() {
+        let dir = test_dir("parents");
+        let store_dir = dir.join("a/b");
+        std::fs::create_dir_all(&store_dir).unwrap();
+        let store = store_dir.join("store.json");
+        std::fs::write(&store, "{}").unwrap();
+        chmod(&store, 0o600);
+        assert_eq!(problems(&store), Vec::<String>::new());
+
+        chmod(&dir.join("a"), 0o777);
+        let found = problems(&store);
+        assert_eq!(found.len(), 1, "{:?}", found);
+        assert!(found[0].contains("/a\""), "{:?}", found);
+
+        // Only the owner of a file can replace it in a sticky directory, like /tmp
+        chmod(&dir.join("a"), 0o1777);
+        assert_eq!(problems(&store), Vec::<String>::new());
+    }
+
+    #[test]
+    fn files_in_directory_stores_are_checked() {
+        let dir = test_dir("directory");
+        std::fs::create_dir_all(dir.join("db")).unwrap();
+        std::fs::write(dir.join("db/password"), "secret").unwrap();
+        chmod(&dir.join("db/password"), 0o600);
+        assert_eq!(problems(&dir), Vec::<String>::new());
+
+        chmod(&dir.join("db/password"), 0o644);
+        chmod(&dir.join("db"), 0o777);
+        let found = problems(&dir);
+        assert_eq!(found.len(), 2, "{:?}", found);
+        assert!(found.iter().any(|problem| problem.contains("db/password")));
+    }
+}
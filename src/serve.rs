@@ -0,0 +1,262 @@
+//! Serves a store over a Unix socket, so that many short-lived shims can share it without each
+//! one re-reading and rewriting the store file.
+//!
+//! Each connection carries a single request and its response, each a line of JSON. Clients load
+//! the whole store, and send back only the entries they changed, so two clients that change
+//! different entries at the same time don't undo each other's work. Requests are answered one
+//! at a time, and a client that doesn't send its request within [`TIMEOUT`] is dropped.
+//!
+//! Only whoever started the server can shut it down, with the token from
+//! [`Server::shutdown_token`]. Otherwise it runs until it is killed.
+
+use crate::commands;
+use crate::context::Context;
+use crate::error::{Error, Result};
+use crate::store::{Diff, Store};
+use serde::{Deserialize, Serialize};
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// The environment variable that holds the socket of the server to use
+pub const SOCKET_VAR: &str = "IMPOSTER_PASS_SOCKET";
+
+/// How long a client has to send its request, and take the response, before it is dropped
+pub const TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "request", rename_all = "lowercase")]
+pub enum Request {
+    /// Asks for the whole store
+    Load,
+
+    /// Changes some entries in the store
+    Apply { diff: Diff },
+
+    /// Stops the server, which replies with the final store
+    Shutdown { token: String },
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "response", rename_all = "lowercase")]
+pub enum Response {
+    Store { store: Store },
+    Ok,
+    Error { message: String },
+}
+
+/// Sends `request` to the server listening on `socket`
+pub fn request(socket: &Path, request: &Request) -> Result<Response> {
+    let mut stream = UnixStream::connect(socket)?;
+
+    serde_json::to_writer(&mut stream, request)?;
+    stream.write_all(b"\n")?;
+    stream.flush()?;
+
+    let mut line = String::new();
+    BufReader::new(stream).read_line(&mut line)?;
+
+    match serde_json::from_str(&line)? {
+        Response::Error { message } => Err(Error::Server(message)),
+        response => Ok(response),
+    }
+}
+
+/// Loads the store from the server listening on `socket`
+pub fn load(socket: &Path) -> Result<Store> {
+    match request(socket, &Request::Load)? {
+        Response::Store { store } => Ok(store),
+        response => Err(Error::Server(format!("unexpected response {:?}", response))),
+    }
+}
+
+/// Stops the server listening on `socket`, and returns the store it was serving
+pub fn shutdown(socket: &Path, token: &str) -> Result<Store> {
+    let token = token.to_string();
+    match request(socket, &Request::Shutdown { token })? {
+        Response::Store { store } => Ok(store),
+        response => Err(Error::Server(format!("unexpected response {:?}", response))),
+    }
+}
+
+pub struct Server {
+    socket: PathBuf,
+    listener: UnixListener,
+
+    /// The store being served is kept in `ctx.store`, and saved wherever `ctx` says
+    ctx: Context,
+
+    /// Made up when the server starts, and needed to shut it down
+    token: String,
+}
+
+impl Server {
+    /// Starts listening on `socket`. Requests aren't answered until [`Server::run`] is called,
+    /// but clients can connect straight away.
+    pub fn bind(socket: impl Into<PathBuf>, ctx: Context) -> Result<Self> {
+        let socket = socket.into();
+
+        // A socket left behind by a server that was killed can be replaced, but one that is
+        // still being served can't
+        if socket.exists() {
+            if UnixStream::connect(&socket).is_ok() {
+                return Err(Error::Usage(format!(
+                    "{} is already being served",
+                    socket.display()
+                )));
+            }
+            std::fs::remove_file(&socket)?;
+        }
+
+        // Anyone who can connect can read every entry, so the socket is made in a directory that
+        // only we can get into, and only moved into place once only we can connect to it
+        let private_dir = {
+            let mut name = socket.clone().into_os_string();
+            name.push(format!(".{}.tmp", std::process::id()));
+            PathBuf::from(name)
+        };
+        {
+            use std::os::unix::fs::DirBuilderExt;
+            std::fs::DirBuilder::new()
+                .mode(0o700)
+                .create(&private_dir)?;
+        }
+        let listener = bind_private(&private_dir.join("socket"), &socket);
+        let _ = std::fs::remove_dir_all(&private_dir);
+        let listener = listener?;
+
+        Ok(Self {
+            socket,
+            listener,
+            ctx,
+            token: commands::generate_password(32, false).to_string(),
+        })
+    }
+
+    /// The token that a [`Request::Shutdown`] must carry. Clients don't get it.
+    pub fn shutdown_token(&self) -> &str {
+        &self.token
+    }
+
+    /// Answers requests until a client asks the server to shut down, and then returns the store
+    pub fn run(mut self) -> Result<Store> {
+        if !self.ctx.quiet {
+            eprintln!("Serving the store on {}", self.socket.display());
+        }
+
+        loop {
+            let (stream, _) = self.listener.accept()?;
+
+            // A client that goes away shouldn't take the server down with it
+            match self.answer(stream) {
+                Ok(true) => return Ok(std::mem::take(&mut self.ctx.store)),
+                Ok(false) => {}
+                Err(err) => {
+                    if !self.ctx.quiet {
+                        eprintln!("Warning: failed to answer a request: {}", err);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Answers the request on `stream`, and returns whether the server should shut down
+    fn answer(&mut self, stream: UnixStream) -> Result<bool> {
+        // Requests are answered one at a time, so a client that never sends one mustn't keep the
+        // others waiting
+        stream.set_read_timeout(Some(TIMEOUT))?;
+        stream.set_write_timeout(Some(TIMEOUT))?;
+
+        let mut line = String::new();
+        BufReader::new(&stream).read_line(&mut line)?;
+
+        let (response, shutdown) = match serde_json::from_str(&line) {
+            Ok(request) => {
+                let shutdown = matches!(request, Request::Shutdown { .. });
+                match self.respond(request) {
+                    Ok(response) => (response, shutdown),
+                    Err(err) => (
+                        Response::Error {
+                            message: err.to_string(),
+                        },
+                        false,
+                    ),
+                }
+            }
+            Err(err) => (
+                Response::Error {
+                    message: format!("bad request: {}", err),
+                },
+                false,
+            ),
+        };
+
+        let mut stream = &stream;
+        serde_json::to_writer(&mut stream, &response)?;
+        stream.write_all(b"\n")?;
+
+        Ok(shutdown)
+    }
+
+    /// Clients only get to see and change what the policy allows, just as if they had loaded the
+    /// store themselves
+    fn respond(&mut self, request: Request) -> Result<Response> {
+        match request {
+            Request::Load => Ok(Response::Store {
+                store: self.ctx.policy.visible(&self.ctx.store)?,
+            }),
+            Request::Shutdown { token } => {
+                // Compare every byte, so that the time taken doesn't give away how much matched
+                let matches = token.len() == self.token.len()
+                    && token
+                        .bytes()
+                        .zip(self.token.bytes())
+                        .fold(0, |diff, (a, b)| diff | (a ^ b))
+                        == 0;
+                if !matches {
+                    return Err(Error::Server("wrong shutdown token".to_string()));
+                }
+
+                Ok(Response::Store {
+                    store: self.ctx.policy.visible(&self.ctx.store)?,
+                })
+            }
+            Request::Apply { diff } => {
+                // Entries are only ever changed under the names the store gives them, which are
+                // the names the policy is checked against
+                let paths = diff.set.keys().chain(&diff.removed).chain(diff.read.keys());
+                for path in paths {
+                    if Store::normalize(path)? != *path {
+                        return Err(Error::InvalidPath(path.clone()));
+                    }
+                }
+                self.ctx.policy.check_diff(&diff)?;
+
+                // The diff is applied as it was sent, so that it keeps the client's writer and
+                // reads
+                self.ctx.apply(diff.clone())?;
+                self.ctx.store.apply(&diff);
+                Ok(Response::Ok)
+            }
+        }
+    }
+}
+
+/// Listens on `path`, which has to be in a directory only we can get into, and then moves the
+/// socket to `socket` once only its owner can connect to it
+fn bind_private(path: &Path, socket: &Path) -> Result<UnixListener> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let listener = UnixListener::bind(path)?;
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))?;
+    std::fs::rename(path, socket)?;
+
+    Ok(listener)
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.socket);
+    }
+}
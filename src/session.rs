@@ -0,0 +1,281 @@
+use crate::cli::Cli;
+use crate::error::Result;
+use crate::source;
+use serde::{Deserialize, Serialize};
+use std::io::{Read, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+/// If set, every call to the pass shim is recorded to the journal at this path
+pub const RECORD_VAR: &str = "IMPOSTER_PASS_RECORD";
+
+/// If set, calls to the pass shim are answered from the session file at this path
+pub const REPLAY_VAR: &str = "IMPOSTER_PASS_REPLAY";
+
+/// Keeps track of which recorded calls have been replayed, and which calls were unexpected
+pub const REPLAY_STATE_VAR: &str = "IMPOSTER_PASS_REPLAY_STATE";
+
+/// A single call to pass, along with everything it printed
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Invocation {
+    /// The arguments pass was called with, not including the program name
+    pub args: Vec<String>,
+    pub stdin: String,
+    pub stdout: String,
+    pub stderr: String,
+    pub exit_code: i32,
+}
+
+/// Every call to pass that a program made, in the order it made them
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Session {
+    pub invocations: Vec<Invocation>,
+}
+
+impl Session {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        Ok(serde_json::from_reader(std::fs::File::open(path)?)?)
+    }
+
+    /// Saves the session where only its owner can read it, since it holds whatever the
+    /// program was given by pass
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        source::write_json(path.as_ref(), self)
+    }
+
+    /// Collects the invocations appended to a journal by [`record`]
+    pub fn from_journal(path: impl AsRef<Path>) -> Result<Self> {
+        Ok(Self {
+            invocations: read_lines(path)?,
+        })
+    }
+}
+
+/// The arguments this process was called with, not including the program name
+fn current_args() -> Vec<String> {
+    std::env::args_os()
+        .skip(1)
+        .map(|arg| arg.to_string_lossy().into_owned())
+        .collect()
+}
+
+/// Appends `item` to the JSON lines file at `path`, which only its owner can read
+fn append_line(path: impl AsRef<Path>, item: &impl Serialize) -> Result<()> {
+    let mut line = serde_json::to_vec(item)?;
+    line.push(b'\n');
+
+    let mut options = std::fs::OpenOptions::new();
+    options.create(true).append(true);
+    #[cfg(target_family = "unix")]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+
+    options.open(path)?.write_all(&line)?;
+
+    Ok(())
+}
+
+/// Reads every item in the JSON lines file at `path`
+fn read_lines<T: serde::de::DeserializeOwned>(path: impl AsRef<Path>) -> Result<Vec<T>> {
+    let text = match std::fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(err) => return Err(err.into()),
+    };
+
+    text.lines()
+        .filter(|line| !line.is_empty())
+        .map(|line| Ok(serde_json::from_str(line)?))
+        .collect()
+}
+
+/// Copies everything from `from` to `to` as it arrives, keeping a copy of it in `captured`
+fn tee(mut from: impl Read, mut to: impl Write, captured: &Mutex<Vec<u8>>) -> Result<()> {
+    let mut buffer = [0; 4096];
+
+    loop {
+        let read = from.read(&mut buffer)?;
+        if read == 0 {
+            return Ok(());
+        }
+
+        captured
+            .lock()
+            .expect("capture lock poisoned")
+            .extend_from_slice(&buffer[..read]);
+        to.write_all(&buffer[..read])?;
+        to.flush()?;
+    }
+}
+
+/// Runs this pass invocation for real, and appends it to the `journal`.
+///
+/// The command is run by calling ourselves again (without recording turned on), passing through
+/// stdout and stderr while keeping a copy of them. stdin is only copied and kept for commands
+/// that read it, so that recording doesn't take input meant for whoever called us. Returns the
+/// exit code of the real invocation.
+pub fn record(journal: &Path) -> Result<i32> {
+    use std::process::{Command, Stdio};
+
+    let args = current_args();
+    // Arguments that don't parse fail before anything is read
+    let reads_stdin = Cli::try_from_pass_args(std::env::args_os())
+        .map(|cli| cli.cmd.reads_stdin())
+        .unwrap_or(false);
+
+    let mut cmd = Command::new(std::env::current_exe()?);
+    #[cfg(target_family = "unix")]
+    {
+        use std::os::unix::process::CommandExt;
+        cmd.arg0(std::env::args_os().next().unwrap_or_default());
+    }
+    let mut child = cmd
+        .args(std::env::args_os().skip(1))
+        .env_remove(RECORD_VAR)
+        .stdin(if reads_stdin {
+            Stdio::piped()
+        } else {
+            Stdio::inherit()
+        })
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+
+    let stdin = Arc::new(Mutex::new(Vec::new()));
+    let stdout = Arc::new(Mutex::new(Vec::new()));
+    let stderr = Arc::new(Mutex::new(Vec::new()));
+
+    // Nothing waits for this thread. Whoever called us might never close stdin, and we don't want
+    // to hang around after the real command is done.
+    if let Some(child_stdin) = child.stdin.take() {
+        let stdin = stdin.clone();
+        std::thread::spawn(move || tee(std::io::stdin(), child_stdin, &stdin));
+    }
+
+    let stdout_thread = {
+        let child_stdout = child.stdout.take().expect("stdout is piped");
+        let stdout = stdout.clone();
+        std::thread::spawn(move || tee(child_stdout, std::io::stdout(), &stdout))
+    };
+    let stderr_thread = {
+        let child_stderr = child.stderr.take().expect("stderr is piped");
+        let stderr = stderr.clone();
+        std::thread::spawn(move || tee(child_stderr, std::io::stderr(), &stderr))
+    };
+
+    let status = child.wait()?;
+    stdout_thread.join().expect("stdout thread panicked")?;
+    stderr_thread.join().expect("stderr thread panicked")?;
+
+    let captured = |buffer: &Mutex<Vec<u8>>| {
+        String::from_utf8_lossy(&buffer.lock().expect("capture lock poisoned")).into_owned()
+    };
+
+    let exit_code = status.code().unwrap_or(1);
+    append_line(
+        journal,
+        &Invocation {
+            args,
+            stdin: captured(&stdin),
+            stdout: captured(&stdout),
+            stderr: captured(&stderr),
+            exit_code,
+        },
+    )?;
+
+    Ok(exit_code)
+}
+
+/// A call that was answered (or refused) during a replay
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Replayed {
+    /// The recorded invocation at this index was played back
+    Matched(usize),
+
+    /// A call that didn't match anything in the session
+    Unexpected { args: Vec<String>, stdin: String },
+}
+
+/// Answers this pass invocation from the `session` file, without touching any store.
+///
+/// The first recorded invocation with the same arguments that hasn't been used yet is played
+/// back. If it was recorded with input, our stdin has to match too. Calls that don't match
+/// anything fail, and are noted in the `state` file so that fool can report them.
+pub fn replay(session: &Path, state: &Path) -> Result<i32> {
+    let session = Session::load(session)?;
+    let args = current_args();
+
+    let used: Vec<usize> = read_lines::<Replayed>(state)?
+        .into_iter()
+        .filter_map(|replayed| match replayed {
+            Replayed::Matched(index) => Some(index),
+            Replayed::Unexpected { .. } => None,
+        })
+        .collect();
+
+    let candidate = session
+        .invocations
+        .iter()
+        .enumerate()
+        .find(|(index, invocation)| invocation.args == args && !used.contains(index));
+
+    // Only read stdin when the recording says there's something on it, so we don't block forever
+    // waiting on a terminal
+    let mut stdin = String::new();
+    if candidate.map(|(_, invocation)| !invocation.stdin.is_empty()) == Some(true) {
+        std::io::stdin().read_to_string(&mut stdin)?;
+    }
+
+    match candidate {
+        Some((index, invocation)) if invocation.stdin == stdin => {
+            append_line(state, &Replayed::Matched(index))?;
+
+            std::io::stdout().write_all(invocation.stdout.as_bytes())?;
+            std::io::stdout().flush()?;
+            std::io::stderr().write_all(invocation.stderr.as_bytes())?;
+
+            Ok(invocation.exit_code)
+        }
+        _ => {
+            eprintln!(
+                "Error: unexpected call to pass with arguments {:?} during replay",
+                args
+            );
+            append_line(state, &Replayed::Unexpected { args, stdin })?;
+
+            Ok(1)
+        }
+    }
+}
+
+/// Prints a summary of a finished replay to stderr, and returns the number of unexpected calls
+pub fn report_replay(session: &Path, state: &Path) -> Result<usize> {
+    let session = Session::load(session)?;
+    let replayed: Vec<Replayed> = read_lines(state)?;
+
+    let mut unexpected = 0;
+    let mut used = Vec::new();
+    for replayed in replayed {
+        match replayed {
+            Replayed::Matched(index) => used.push(index),
+            Replayed::Unexpected { args, .. } => {
+                unexpected += 1;
+                eprintln!("Unexpected call to pass: {:?}", args);
+            }
+        }
+    }
+
+    for (index, invocation) in session.invocations.iter().enumerate() {
+        if !used.contains(&index) {
+            eprintln!(
+                "Recorded call to pass was never made: {:?}",
+                invocation.args
+            );
+        }
+    }
+
+    Ok(unexpected)
+}
@@ -0,0 +1,334 @@
+use crate::error::{Error, Result};
+use crate::integrity;
+use crate::lock::Lock;
+use crate::seal;
+use crate::secret::Secret;
+use crate::store::Store;
+use serde::Serialize;
+use std::fmt::{self, Display};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// How many old versions of a store file to keep when it is saved, as `<file>.1` (the newest)
+/// to `<file>.N`. None are kept if this isn't set.
+pub const BACKUPS_VAR: &str = "IMPOSTER_PASS_BACKUPS";
+
+/// Somewhere that a store is loaded from, as given to --store
+#[derive(Debug, Clone)]
+pub enum Source {
+    /// A json object given directly. Changes can't be saved.
+    Inline(Store),
+
+    /// A store given directly, sealed with a passphrase by `imposter-pass seal`. Changes can't
+    /// be saved.
+    Sealed(Store),
+
+    /// A json file, which is created the first time the store is saved
+    File(PathBuf),
+
+    /// A directory where every file is an entry, named after its path in the directory.
+    ///
+    /// This is how systemd (`$CREDENTIALS_DIRECTORY`), Docker (`/run/secrets`) and Kubernetes
+    /// secret volumes hand secrets to a program. These are read-only, so changes can't be saved.
+    Directory(PathBuf),
+}
+
+impl Source {
+    /// Works out what kind of source the value of --store is.
+    ///
+    /// A json array is a list of sources, each of which is a path or a json object.
+    pub fn parse(store_opt: &str) -> Result<Vec<Self>> {
+        let store_opt: serde_json::Value = serde_json::from_str(store_opt)
+            .unwrap_or_else(|_| serde_json::Value::String(store_opt.to_string()));
+
+        match store_opt {
+            serde_json::Value::Array(store_opts) => {
+                store_opts.into_iter().map(Self::from_value).collect()
+            }
+            store_opt => Ok(vec![Self::from_value(store_opt)?]),
+        }
+    }
+
+    fn from_value(store_opt: serde_json::Value) -> Result<Self> {
+        Ok(match store_opt.as_str() {
+            Some(text) if seal::is_sealed(text) => Source::Sealed(seal::open(text)?),
+            Some(path) if Path::new(path).is_dir() => Source::Directory(PathBuf::from(path)),
+            Some(path) => Source::File(PathBuf::from(path)),
+            None => Source::Inline(serde_json::from_value(store_opt)?),
+        })
+    }
+
+    /// Whether `save` actually writes anything
+    pub fn is_writable(&self) -> bool {
+        matches!(self, Source::File(_))
+    }
+
+    pub fn load(&self) -> Result<Store> {
+        let store = self.load_any_version()?;
+        store.check_version()?;
+        Ok(store)
+    }
+
+    fn load_any_version(&self) -> Result<Store> {
+        match self {
+            Source::Inline(store) | Source::Sealed(store) => Ok(store.clone()),
+            Source::File(path) if path.exists() => integrity::open(
+                path,
+                serde_json::from_reader(std::fs::File::open(path)?)?,
+                integrity::key()?.as_deref().map(Vec::as_slice),
+            ),
+            Source::File(_) => Ok(Store::default()),
+            Source::Directory(path) => {
+                let mut store = Store::default();
+                read_dir(path, path, &mut Vec::new(), &mut store)?;
+                Ok(store)
+            }
+        }
+    }
+
+    /// Locks the source against being saved by anyone else, if it can be written to
+    pub fn lock(&self) -> Result<Option<Lock>> {
+        match self {
+            Source::File(path) => Ok(Some(Lock::acquire(path)?)),
+            _ => Ok(None),
+        }
+    }
+
+    /// Saves `store`, if this source can be written to. The old file is only backed up if
+    /// `backup` is set.
+    pub fn save(&self, store: &Store, backup: bool) -> Result<()> {
+        if let Source::File(path) = self {
+            let backups = match std::env::var(BACKUPS_VAR) {
+                Ok(backups) => backups.parse().map_err(|_| {
+                    Error::Usage(format!(
+                        "{} must be a number, not {:?}",
+                        BACKUPS_VAR, backups
+                    ))
+                })?,
+                Err(_) => 0,
+            };
+            let path = &resolve_link(path);
+            if backup && backups > 0 && path.exists() {
+                rotate_backups(path, backups)?;
+            }
+
+            match integrity::key()? {
+                Some(key) => write_json(path, &integrity::seal(&key, store)?)?,
+                None => write_json(path, store)?,
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Warns that the store isn't the real pass, and says where it comes from
+    pub fn warn(&self) {
+        match self {
+            Source::Inline(_) => eprintln!("Warning: database will be loaded from the environment variable IMPOSTER_PASS_STORE. THE DATABASE IS NOT ENCRYPTED and changes WILL NOT BE SAVED!"),
+            Source::Sealed(_) => eprintln!("Warning: database will be decrypted from the sealed store in IMPOSTER_PASS_STORE. Changes WILL NOT BE SAVED!"),
+            Source::File(path) => eprintln!("Warning: database will be saved and loaded from {:?}. THE DATABASE IS NOT ENCRYPTED!", path),
+            Source::Directory(path) => eprintln!("Warning: database will be loaded from the files in {:?}. Changes WILL NOT BE SAVED!", path),
+        }
+    }
+}
+
+impl Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Source::Inline(_) => f.write_str("(json)"),
+            Source::Sealed(_) => f.write_str("(sealed)"),
+            Source::File(path) => write!(f, "{}", path.display()),
+            // Joining nothing adds the trailing slash, unless there already is one
+            Source::Directory(path) => write!(f, "{}", path.join("").display()),
+        }
+    }
+}
+
+/// The file that `path` links to, if it is a symlink to one, or else `path` itself
+fn resolve_link(path: &Path) -> PathBuf {
+    let is_link = std::fs::symlink_metadata(path)
+        .map(|metadata| metadata.file_type().is_symlink())
+        .unwrap_or(false);
+
+    match is_link {
+        true => std::fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf()),
+        false => path.to_path_buf(),
+    }
+}
+
+/// Replaces the file at `path` with `value` as json, so that a crash leaves either the old file
+/// or the new one, and never half of each. The file can only be read by its owner.
+///
+/// If `path` is a symlink, the file it links to is replaced instead, so that stores kept in
+/// something like a dotfiles repository stay linked.
+pub fn write_json(path: &Path, value: &impl Serialize) -> Result<()> {
+    let path = &resolve_link(path);
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let file_name = path
+        .file_name()
+        .ok_or_else(|| Error::InvalidPath(path.to_path_buf()))?;
+    std::fs::create_dir_all(dir)?;
+
+    // The new file must be in the same directory, or renaming it wouldn't be atomic
+    let tmp_path = dir.join(format!(
+        ".{}.{}.tmp",
+        file_name.to_string_lossy(),
+        std::process::id()
+    ));
+    if let Err(err) = write_new(&tmp_path, value) {
+        let _ = std::fs::remove_file(&tmp_path);
+        return Err(err);
+    }
+    std::fs::rename(&tmp_path, path)?;
+
+    // Make sure the rename itself has reached the disk
+    #[cfg(target_family = "unix")]
+    std::fs::File::open(dir)?.sync_all()?;
+
+    Ok(())
+}
+
+/// Writes `value` to a new file at `path`, and waits for it to reach the disk
+fn write_new(path: &Path, value: &impl Serialize) -> Result<()> {
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(target_family = "unix")]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+
+    let mut file = options.open(path)?;
+    serde_json::to_writer_pretty(&mut file, value)?;
+    file.flush()?;
+    file.sync_all()?;
+
+    Ok(())
+}
+
+/// Shifts the backups of `path` along by one, dropping the oldest, and makes the current file
+/// the newest backup
+fn rotate_backups(path: &Path, backups: usize) -> Result<()> {
+    let backup = |number: usize| {
+        let mut backup = path.as_os_str().to_owned();
+        backup.push(format!(".{}", number));
+        PathBuf::from(backup)
+    };
+
+    for number in (1..backups).rev() {
+        if backup(number).exists() {
+            std::fs::rename(backup(number), backup(number + 1))?;
+        }
+    }
+
+    let newest = backup(1);
+    match std::fs::remove_file(&newest) {
+        Err(err) if err.kind() != std::io::ErrorKind::NotFound => return Err(err.into()),
+        _ => {}
+    }
+    // A link leaves the current file where it is until the new one replaces it
+    if std::fs::hard_link(path, &newest).is_err() {
+        std::fs::copy(path, &newest)?;
+    }
+
+    Ok(())
+}
+
+/// Adds every file under `dir` to `store`, named by its path relative to `root`.
+///
+/// `parents` holds the real paths of the directories that `dir` is in, so that a link back up to
+/// one of them is skipped instead of being followed forever.
+fn read_dir(root: &Path, dir: &Path, parents: &mut Vec<PathBuf>, store: &mut Store) -> Result<()> {
+    let real_dir = std::fs::canonicalize(dir)?;
+    if parents.contains(&real_dir) {
+        return Ok(());
+    }
+    parents.push(real_dir);
+
+    for dir_entry in std::fs::read_dir(dir)? {
+        let dir_entry = dir_entry?;
+
+        // Kubernetes keeps the real files in hidden folders like ..data, and links to them
+        if dir_entry.file_name().to_string_lossy().starts_with('.') {
+            continue;
+        }
+
+        let path = dir_entry.path();
+        // Follow links, since secret volumes are mostly made of them
+        let metadata = std::fs::metadata(&path)?;
+
+        if metadata.is_dir() {
+            read_dir(root, &path, parents, store)?;
+        } else if metadata.is_file() {
+            if let Ok(name) = path.strip_prefix(root) {
+                *store.value_mut(name)? = Secret::from_bytes(std::fs::read(&path)?);
+            }
+        }
+    }
+
+    parents.pop();
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A fresh directory for one test
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "imposter-pass-source-{}-{}",
+            name,
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn writing_through_a_symlink_keeps_it() {
+        let dir = test_dir("symlink");
+        let target = dir.join("dotfiles").join("store.json");
+        std::fs::create_dir_all(target.parent().unwrap()).unwrap();
+        std::fs::write(&target, "{}").unwrap();
+        let link = dir.join("store.json");
+        std::os::unix::fs::symlink(&target, &link).unwrap();
+
+        write_json(&link, &serde_json::json!({ "a": "1" })).unwrap();
+
+        let is_link = std::fs::symlink_metadata(&link)
+            .unwrap()
+            .file_type()
+            .is_symlink();
+        let written = std::fs::read_to_string(&target).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert!(is_link);
+        assert!(written.contains("\"a\""), "{}", written);
+    }
+
+    #[test]
+    fn directory_symlink_loops_are_not_followed() {
+        let dir = test_dir("loop");
+        std::fs::create_dir_all(dir.join("db")).unwrap();
+        std::fs::write(dir.join("db/password"), "hunter2").unwrap();
+        std::os::unix::fs::symlink(&dir, dir.join("db/loop")).unwrap();
+        std::os::unix::fs::symlink("..", dir.join("db/up")).unwrap();
+        // Links to a directory that isn't a parent are still followed
+        std::os::unix::fs::symlink(dir.join("db"), dir.join("linked")).unwrap();
+
+        let store = Source::Directory(dir.clone()).load();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let store = store.unwrap();
+        let paths: Vec<_> = store.list_paths(None::<&Path>).unwrap().collect();
+        assert_eq!(
+            paths,
+            vec![Path::new("db/password"), Path::new("linked/password")]
+        );
+    }
+}
@@ -1,18 +1,214 @@
 use crate::error::{Error, Result};
-use serde::{Deserialize, Serialize};
-use std::collections::BTreeMap;
+use crate::meta::{Meta, Reads};
+use crate::secret::Secret;
+use chrono::Utc;
+use serde::{Deserialize, Serialize, Serializer};
+use std::collections::{BTreeMap, BTreeSet};
 use std::fmt::{self, Display};
 use std::path::{Path, PathBuf};
 use std::str::FromStr;
 
-#[derive(Clone, Default, Debug, Serialize, Deserialize, Hash, PartialEq, Eq)]
-#[serde(transparent)]
+/// The version of the format that stores are saved in.
+///
+/// - Version 0 is a bare json object mapping each path to its value, with metadata entries like
+///   `.gpg-id` mixed in. What is known about each entry isn't kept.
+/// - Version 1 is `{"version": 1, "entries": {...}, "meta": {...}}`, where `meta` holds the
+///   metadata entries.
+pub const VERSION: u32 = 1;
+
+#[derive(Clone, Debug, Deserialize, Hash, PartialEq, Eq)]
+#[serde(from = "SavedStore")]
 pub struct Store {
-    store: BTreeMap<PathBuf, String>,
+    store: BTreeMap<PathBuf, Entry>,
+
+    /// The version of the format the store was loaded in. It is saved in the same version, so
+    /// that older versions of imposter-pass can still read it, until it is migrated. Version 0
+    /// stores are saved without what is known about their entries, since those versions
+    /// only understand plain strings.
+    version: u32,
+}
+
+impl Default for Store {
+    fn default() -> Self {
+        Self {
+            store: BTreeMap::new(),
+            version: VERSION,
+        }
+    }
+}
+
+/// How a store is saved, in any version of the format
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum SavedStore {
+    // Entries are never numbers, so a bare store can't be mistaken for this
+    Versioned {
+        version: u32,
+        entries: BTreeMap<PathBuf, Entry>,
+        #[serde(default)]
+        meta: BTreeMap<PathBuf, Entry>,
+    },
+    Bare(BTreeMap<PathBuf, Entry>),
+}
+
+impl From<SavedStore> for Store {
+    fn from(saved: SavedStore) -> Self {
+        match saved {
+            SavedStore::Versioned {
+                version,
+                mut entries,
+                meta,
+            } => {
+                entries.extend(meta);
+                Self {
+                    store: entries,
+                    version,
+                }
+            }
+            SavedStore::Bare(store) => Self { store, version: 0 },
+        }
+    }
+}
+
+impl Serialize for Store {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        #[derive(Serialize)]
+        struct Versioned<'a> {
+            version: u32,
+            entries: BTreeMap<&'a Path, &'a Entry>,
+            meta: BTreeMap<&'a Path, &'a Entry>,
+        }
+
+        if self.version == 0 {
+            return self
+                .store
+                .iter()
+                .map(|(path, entry)| (path, &entry.value))
+                .collect::<BTreeMap<_, _>>()
+                .serialize(serializer);
+        }
+
+        let (meta, entries) = self
+            .store
+            .iter()
+            .map(|(path, entry)| (path.as_path(), entry))
+            .partition(|&(path, _)| Store::is_metadata(path));
+        Versioned {
+            version: self.version,
+            entries,
+            meta,
+        }
+        .serialize(serializer)
+    }
+}
+
+/// The value of an entry, along with what is known about it
+#[derive(Clone, Default, Debug, Deserialize, Hash, PartialEq, Eq)]
+#[serde(from = "SavedEntry")]
+pub struct Entry {
+    pub value: Secret,
+    pub meta: Meta,
+}
+
+/// How an entry is saved. Entries without metadata are plain strings, so stores from before
+/// there was any still load.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum SavedEntry {
+    Plain(Secret),
+    WithMeta {
+        value: Secret,
+        #[serde(flatten)]
+        meta: Meta,
+    },
+}
+
+impl From<SavedEntry> for Entry {
+    fn from(saved: SavedEntry) -> Self {
+        match saved {
+            SavedEntry::Plain(value) => Self {
+                value,
+                meta: Meta::default(),
+            },
+            SavedEntry::WithMeta { value, meta } => Self { value, meta },
+        }
+    }
+}
+
+impl Serialize for Entry {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        #[derive(Serialize)]
+        struct WithMeta<'a> {
+            value: &'a Secret,
+            #[serde(flatten)]
+            meta: &'a Meta,
+        }
+
+        if self.meta.is_empty() {
+            self.value.serialize(serializer)
+        } else {
+            WithMeta {
+                value: &self.value,
+                meta: &self.meta,
+            }
+            .serialize(serializer)
+        }
+    }
+}
+
+/// The changes that turn one store into another
+#[derive(Clone, Default, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Diff {
+    /// Entries that were added or changed, with their new values
+    #[serde(default)]
+    pub set: BTreeMap<PathBuf, Secret>,
+
+    /// Entries that were removed
+    #[serde(default)]
+    pub removed: BTreeSet<PathBuf>,
+
+    /// Entries whose values were read
+    #[serde(default)]
+    pub read: BTreeMap<PathBuf, Reads>,
+
+    /// The command, or the fooled program, that made the changes
+    #[serde(default)]
+    pub writer: Option<String>,
+}
+
+impl Diff {
+    pub fn is_empty(&self) -> bool {
+        self.read.is_empty() && !self.changes_values()
+    }
+
+    /// Whether any entry is set or removed, rather than only read
+    pub fn changes_values(&self) -> bool {
+        !self.set.is_empty() || !self.removed.is_empty()
+    }
 }
 
 impl Store {
-    fn normalize(path: impl AsRef<Path>) -> Result<PathBuf> {
+    /// The version of the format the store was loaded in
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+
+    /// Fails if the store was saved in a version of the format that is too new to understand
+    pub fn check_version(&self) -> Result<()> {
+        if self.version > VERSION {
+            return Err(Error::UnknownVersion(self.version));
+        }
+
+        Ok(())
+    }
+
+    /// Makes the store be saved in the current version of the format from now on
+    pub fn migrate(&mut self) {
+        self.version = VERSION;
+    }
+
+    /// The path an entry is kept at, which is `path` without any `.` components
+    pub fn normalize(path: impl AsRef<Path>) -> Result<PathBuf> {
         use std::path::Component;
         path.as_ref()
             .components()
@@ -24,20 +220,100 @@ impl Store {
             .collect::<Result<PathBuf>>()
     }
 
+    /// Entries with a name starting with `.` hold metadata rather than passwords, like the
+    /// `.gpg-id` file in a real password store. They are left out of listings.
+    pub fn is_metadata(path: &Path) -> bool {
+        path.components().any(|component| {
+            component
+                .as_os_str()
+                .to_str()
+                .map(|name| name.starts_with('.'))
+                .unwrap_or(false)
+        })
+    }
+
     pub fn list(
         &self,
         start: Option<impl AsRef<Path>>,
     ) -> Result<impl Iterator<Item = (&Path, &str)>> {
+        Ok(self
+            .list_entries(start)?
+            .map(|(path, entry)| (path, entry.value.as_str())))
+    }
+
+    /// Like `list`, but with what is known about each entry as well as its value
+    pub fn list_entries(
+        &self,
+        start: Option<impl AsRef<Path>>,
+    ) -> Result<impl Iterator<Item = (&Path, &Entry)>> {
         let start = start.map(Self::normalize).transpose()?;
 
         Ok(self
             .store
             .iter()
+            .filter(|&(path, _)| !Self::is_metadata(path))
             .filter(move |&(path, _)| match &start {
                 Some(start) => path.starts_with(start),
                 None => true,
             })
-            .map(|(path, value)| (path.as_path(), value.as_str())))
+            .map(|(path, entry)| (path.as_path(), entry)))
+    }
+
+    /// The value of the entry at `path`, if there is one
+    pub fn get(&self, path: impl AsRef<Path>) -> Result<Option<&str>> {
+        Ok(self
+            .store
+            .get(&Self::normalize(path)?)
+            .map(|entry| entry.value.as_str()))
+    }
+
+    /// What is known about the entry at `path`, if there is one
+    pub fn meta(&self, path: impl AsRef<Path>) -> Result<Option<&Meta>> {
+        Ok(self
+            .store
+            .get(&Self::normalize(path)?)
+            .map(|entry| &entry.meta))
+    }
+
+    /// The value of the entry at `path`, which is added with an empty value if it isn't there
+    pub fn value_mut(&mut self, path: impl AsRef<Path>) -> Result<&mut Secret> {
+        Ok(&mut self.store.entry(Self::normalize(path)?).or_default().value)
+    }
+
+    /// Adds `entry` at `path` as it is, replacing any entry that is already there
+    pub fn insert(&mut self, path: PathBuf, entry: Entry) {
+        self.store.insert(path, entry);
+    }
+
+    /// The metadata stored under `key`, which must start with a `.`
+    pub fn metadata(&self, key: &str) -> Option<&str> {
+        debug_assert!(key.starts_with('.'));
+        self.store
+            .get(Path::new(key))
+            .map(|entry| entry.value.as_str())
+    }
+
+    pub fn set_metadata(&mut self, key: &str, value: String) {
+        debug_assert!(key.starts_with('.'));
+        self.store.insert(
+            PathBuf::from(key),
+            Entry {
+                value: value.into(),
+                meta: Meta::default(),
+            },
+        );
+    }
+
+    pub fn remove_metadata(&mut self, key: &str) {
+        debug_assert!(key.starts_with('.'));
+        self.store.remove(Path::new(key));
+    }
+
+    /// Every entry, including the metadata that `list` leaves out
+    pub fn entries(&self) -> impl Iterator<Item = (&Path, &Entry)> {
+        self.store
+            .iter()
+            .map(|(path, entry)| (path.as_path(), entry))
     }
 
     pub fn list_paths(
@@ -135,12 +411,74 @@ impl Store {
         Ok(())
     }
 
-    pub fn entry(
-        &mut self,
-        path: impl AsRef<Path>,
-    ) -> Result<std::collections::btree_map::Entry<PathBuf, String>> {
-        Ok(self.store.entry(Self::normalize(path)?))
+    /// The changes that turn this store into `new`, including changes to metadata entries and
+    /// the reads that `new` has counted since
+    pub fn diff(&self, new: &Store) -> Diff {
+        Diff {
+            set: new
+                .store
+                .iter()
+                .filter(|&(path, entry)| {
+                    self.store.get(path).map(|old| &old.value) != Some(&entry.value)
+                })
+                .map(|(path, entry)| (path.clone(), entry.value.clone()))
+                .collect(),
+            removed: self
+                .store
+                .keys()
+                .filter(|path| !new.store.contains_key(*path))
+                .cloned()
+                .collect(),
+            read: new
+                .store
+                .iter()
+                .filter_map(|(path, entry)| {
+                    let old = self.store.get(path).map(|old| &old.meta);
+                    Some((path.clone(), entry.meta.reads_since(old)?))
+                })
+                .collect(),
+            writer: None,
+        }
     }
+
+    /// Makes the changes in `diff`, leaving every other entry as it is. Entries that are set
+    /// are stamped with the time and `diff.writer`, and reads are counted.
+    pub fn apply(&mut self, diff: &Diff) {
+        let now = Utc::now();
+
+        for path in &diff.removed {
+            self.store.remove(path);
+        }
+
+        for (path, value) in &diff.set {
+            let entry = self.store.entry(path.clone()).or_default();
+            entry.value = value.clone();
+            // Metadata entries are only there for imposter-pass itself
+            if !Self::is_metadata(path) {
+                entry.meta.written(diff.writer.as_deref(), now);
+            }
+        }
+
+        for (path, reads) in &diff.read {
+            if let Some(entry) = self.store.get_mut(path) {
+                entry.meta.read(*reads);
+            }
+        }
+    }
+}
+
+/// Splits the value of an entry into its password, which is the first line, and the fields on
+/// the lines after it, which are written as `key: value`
+pub fn parse_entry(value: &str) -> (&str, BTreeMap<&str, &str>) {
+    let mut lines = value.lines();
+    let password = lines.next().unwrap_or_default();
+
+    let fields = lines
+        .filter_map(|line| line.split_once(':'))
+        .map(|(key, value)| (key.trim(), value.trim()))
+        .collect();
+
+    (password, fields)
 }
 
 impl FromStr for Store {
@@ -160,3 +498,83 @@ impl Display for Store {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A store that was saved with `saved`, after `pass insert` set `a`
+    fn inserted(saved: &str) -> Store {
+        let mut store: Store = saved.parse().unwrap();
+        let mut diff = Diff::default();
+        diff.set.insert("a".into(), "new".to_string().into());
+        diff.writer = Some("pass insert".to_string());
+        store.apply(&diff);
+        store
+    }
+
+    fn saved(store: &Store) -> serde_json::Value {
+        serde_json::to_value(store).unwrap()
+    }
+
+    #[test]
+    fn bare_stores_are_version_0() {
+        let store: Store = r#"{"a": "1", ".gpg-id": "k"}"#.parse().unwrap();
+
+        assert_eq!(store.version(), 0);
+        assert_eq!(store.get("a").unwrap(), Some("1"));
+        assert_eq!(store.metadata(".gpg-id"), Some("k"));
+    }
+
+    #[test]
+    fn version_0_is_saved_with_plain_strings() {
+        let store = inserted(r#"{"a": "1", "b": {"value": "2", "reads": 3}, ".gpg-id": "k"}"#);
+
+        assert!(!store.meta("a").unwrap().unwrap().is_empty());
+        assert_eq!(
+            saved(&store),
+            serde_json::json!({"a": "new", "b": "2", ".gpg-id": "k"})
+        );
+    }
+
+    #[test]
+    fn version_1_keeps_metadata() {
+        let store = inserted(r#"{"version": 1, "entries": {"b": "2"}, "meta": {".gpg-id": "k"}}"#);
+        let saved = saved(&store);
+
+        assert_eq!(saved["version"], 1);
+        assert_eq!(saved["entries"]["a"]["value"], "new");
+        assert_eq!(saved["entries"]["a"]["writer"], "pass insert");
+        assert_eq!(saved["entries"]["b"], "2");
+        assert_eq!(saved["meta"][".gpg-id"], "k");
+
+        let reloaded: Store = saved.to_string().parse().unwrap();
+        assert_eq!(reloaded, store);
+    }
+
+    #[test]
+    fn migrate_saves_the_current_version() {
+        let mut store = inserted(r#"{"a": "1", ".gpg-id": "k"}"#);
+        store.migrate();
+        let saved = saved(&store);
+
+        assert_eq!(saved["version"], VERSION);
+        assert_eq!(saved["entries"]["a"]["writer"], "pass insert");
+        assert_eq!(saved["meta"][".gpg-id"], "k");
+    }
+
+    #[test]
+    fn new_stores_are_the_current_version() {
+        assert_eq!(Store::default().version(), VERSION);
+        assert_eq!(saved(&Store::default())["version"], VERSION);
+    }
+
+    #[test]
+    fn newer_versions_are_refused() {
+        let store: Store = r#"{"version": 2, "entries": {}}"#.parse().unwrap();
+        assert!(store.check_version().is_err());
+
+        let store: Store = r#"{"version": 1, "entries": {}}"#.parse().unwrap();
+        assert!(store.check_version().is_ok());
+    }
+}
@@ -0,0 +1,290 @@
+use crate::audit;
+use crate::error::Result;
+use crate::gpg;
+use crate::policy::{Operation, Policy};
+use crate::secret::Secret;
+use crate::store::Store;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use zeroize::Zeroizing;
+
+/// The environment variable pass reads the location of its store from
+pub const PASSWORD_STORE_DIR_VAR: &str = "PASSWORD_STORE_DIR";
+
+/// The extension pass gives to every entry in its store
+const EXTENSION: &str = "gpg";
+
+/// A directory laid out like a real password store, for programs that look at the files rather
+/// than calling pass.
+///
+/// Each entry `a/b` is written to `a/b.gpg`. The files hold the entry itself rather than
+/// encrypted data, so only programs that don't decrypt them (or that use our gpg) will work.
+pub struct Tree {
+    root: PathBuf,
+    written: BTreeMap<PathBuf, Secret>,
+    gpg_id: String,
+}
+
+impl Tree {
+    /// Writes every entry in `store` to a new tree at `root`, along with the key ids in
+    /// `gpg_id`
+    pub fn materialize(root: impl Into<PathBuf>, store: &Store, gpg_id: &str) -> Result<Self> {
+        let root = root.into();
+        std::fs::create_dir_all(&root)?;
+        write_private(&root.join(gpg::GPG_ID), gpg_id)?;
+
+        let mut written = BTreeMap::new();
+        for (path, value) in store.list(None::<&Path>)? {
+            let file = entry_file(&root, path);
+            if let Some(parent) = file.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            write_private(&file, value)?;
+
+            written.insert(path.to_path_buf(), value.into());
+        }
+
+        Ok(Self {
+            root,
+            written,
+            gpg_id: gpg_id.to_string(),
+        })
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Applies the changes that were made to the tree since it was written to `store`.
+    ///
+    /// Entries whose files were changed or added are set, and entries whose files were removed
+    /// are removed. A changed `.gpg-id` replaces the one in the store's metadata. Changes the
+    /// policy doesn't allow are left out with a warning.
+    pub fn sync(&self, store: &mut Store, policy: &Policy, log: Option<&audit::Log>) -> Result<()> {
+        let mut found = BTreeMap::new();
+        self.read_dir(&self.root, &mut found)?;
+
+        if let Ok(gpg_id) = std::fs::read_to_string(self.root.join(gpg::GPG_ID)) {
+            if gpg_id != self.gpg_id {
+                let result = policy.check(Operation::Insert, gpg::GPG_ID);
+                if result.is_ok() {
+                    store.set_metadata(gpg::GPG_ID, gpg_id);
+                }
+                self.report("tree insert", Path::new(gpg::GPG_ID), result, log)?;
+            }
+        }
+
+        let inserted = found
+            .iter()
+            .filter(|&(path, value)| self.written.get(path) != Some(value))
+            .map(|(path, value)| (Operation::Insert, path, Some(value)));
+        let removed = self
+            .written
+            .keys()
+            .filter(|path| !found.contains_key(*path))
+            .map(|path| (Operation::Rm, path, None));
+
+        for (op, path, value) in inserted.chain(removed) {
+            let result = policy.check(op, path).and_then(|_| match value {
+                Some(value) => {
+                    *store.value_mut(path)? = value.clone();
+                    Ok(())
+                }
+                None => store.remove(path),
+            });
+
+            self.report(&format!("tree {}", op), path, result, log)?;
+        }
+
+        Ok(())
+    }
+
+    /// Logs a change that was synced, and warns if it couldn't be
+    fn report(
+        &self,
+        subcommand: &str,
+        path: &Path,
+        result: Result<()>,
+        log: Option<&audit::Log>,
+    ) -> Result<()> {
+        if let Some(log) = log {
+            log.append(&audit::Record::new(subcommand, Some(path), &result))?;
+        }
+
+        if let Err(err) = result {
+            eprintln!(
+                "Warning: a change to {} was not synced: {}",
+                path.display(),
+                err
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Collects every entry under `dir`, keyed by its path in the store
+    fn read_dir(&self, dir: &Path, found: &mut BTreeMap<PathBuf, Secret>) -> Result<()> {
+        for dir_entry in std::fs::read_dir(dir)? {
+            let dir_entry = dir_entry?;
+            let path = dir_entry.path();
+
+            if dir_entry.file_type()?.is_dir() {
+                self.read_dir(&path, found)?;
+            } else if let Some(name) = entry_name(&path) {
+                if let Ok(name) = name.strip_prefix(&self.root) {
+                    // Programs may "encrypt" the files they write with our gpg
+                    let data = Zeroizing::new(std::fs::read(&path)?);
+                    let value = Secret::from_bytes(gpg::dearmor(&data).to_vec());
+                    found.insert(name.to_path_buf(), value);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Removes the tree from the disk
+    pub fn remove(self) -> Result<()> {
+        Ok(std::fs::remove_dir_all(&self.root)?)
+    }
+}
+
+/// The file that the entry `path` is written to in the tree at `root`.
+///
+/// The extension is added to the whole name rather than replacing whatever follows its last
+/// `.`, so `web/github.com` is written to `web/github.com.gpg`.
+fn entry_file(root: &Path, path: &Path) -> PathBuf {
+    let mut file = root.join(path).into_os_string();
+    file.push(".");
+    file.push(EXTENSION);
+    file.into()
+}
+
+/// The entry that `file` holds, which is its path without the trailing `.gpg`, if it has one
+pub fn entry_name(file: &Path) -> Option<PathBuf> {
+    let name = file
+        .file_name()?
+        .to_str()?
+        .strip_suffix(EXTENSION)?
+        .strip_suffix('.')
+        .filter(|name| !name.is_empty())?;
+
+    Some(file.with_file_name(name))
+}
+
+/// Writes a file that only its owner can read
+fn write_private(path: &Path, contents: &str) -> Result<()> {
+    use std::io::prelude::*;
+
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+
+    #[cfg(target_family = "unix")]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+
+    options.open(path)?.write_all(contents.as_bytes())?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NAMES: &[&str] = &[
+        "web/github.com",
+        "web/gitlab.com",
+        "x.com",
+        "x.org",
+        "a.b.c/d.e",
+    ];
+
+    fn test_root(name: &str) -> PathBuf {
+        let root = std::env::temp_dir().join(format!(
+            "imposter-pass-tree-{}-{}",
+            name,
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&root);
+        root
+    }
+
+    fn dotted_store() -> Store {
+        let mut store = Store::default();
+        for name in NAMES {
+            *store.value_mut(name).unwrap() = format!("value of {}", name).into();
+        }
+        store
+    }
+
+    #[test]
+    fn file_names() {
+        let root = Path::new("/tree");
+
+        assert_eq!(
+            entry_file(root, Path::new("web/github.com")),
+            Path::new("/tree/web/github.com.gpg")
+        );
+        assert_eq!(
+            entry_name(Path::new("/tree/web/github.com.gpg")),
+            Some(PathBuf::from("/tree/web/github.com"))
+        );
+        assert_eq!(
+            entry_name(Path::new("/tree/x.gpg.gpg")),
+            Some(PathBuf::from("/tree/x.gpg"))
+        );
+        assert_eq!(entry_name(Path::new("/tree/x.com")), None);
+        assert_eq!(entry_name(Path::new("/tree/xgpg")), None);
+        assert_eq!(entry_name(Path::new("/tree/.gpg")), None);
+    }
+
+    #[test]
+    fn dotted_names_round_trip() {
+        let root = test_root("round-trip");
+        let store = dotted_store();
+        let tree = Tree::materialize(&root, &store, "").unwrap();
+
+        for name in NAMES {
+            assert!(entry_file(&root, Path::new(name)).is_file(), "{}", name);
+        }
+
+        let mut synced = store.clone();
+        tree.sync(&mut synced, &Policy::default(), None).unwrap();
+        tree.remove().unwrap();
+
+        let values = |store: &Store| -> Vec<(PathBuf, String)> {
+            store
+                .list(None::<&Path>)
+                .unwrap()
+                .map(|(path, value)| (path.to_path_buf(), value.to_string()))
+                .collect()
+        };
+        assert_eq!(values(&synced), values(&store));
+    }
+
+    #[test]
+    fn changes_to_dotted_names_are_synced() {
+        let root = test_root("changes");
+        let store = dotted_store();
+        let tree = Tree::materialize(&root, &store, "").unwrap();
+
+        std::fs::write(entry_file(&root, Path::new("x.com")), "changed").unwrap();
+        std::fs::remove_file(entry_file(&root, Path::new("x.org"))).unwrap();
+        std::fs::write(entry_file(&root, Path::new("new.net")), "added").unwrap();
+
+        let mut synced = store.clone();
+        tree.sync(&mut synced, &Policy::default(), None).unwrap();
+        tree.remove().unwrap();
+
+        assert_eq!(synced.get("x.com").unwrap(), Some("changed"));
+        assert_eq!(synced.get("x.org").unwrap(), None);
+        assert_eq!(synced.get("new.net").unwrap(), Some("added"));
+        assert_eq!(
+            synced.get("web/github.com").unwrap(),
+            Some("value of web/github.com")
+        );
+    }
+}
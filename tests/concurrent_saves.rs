@@ -0,0 +1,148 @@
+//! Runs many imposter-pass processes against one store file at once, and checks that none of
+//! their changes are lost.
+
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+
+const PROCESSES: usize = 48;
+
+/// A fresh directory for one test to keep its store in
+fn test_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("imposter-pass-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn imposter_pass(store: &Path) -> Command {
+    let mut cmd = Command::new(env!("CARGO_BIN_EXE_imposter-pass"));
+    cmd.arg("--store")
+        .arg(store)
+        .env("IMPOSTER_PASS_QUIET", "true")
+        .env_remove("IMPOSTER_PASS_AUDIT_LOG")
+        .env_remove("IMPOSTER_PASS_POLICY")
+        .env_remove("IMPOSTER_PASS_SOCKET")
+        .env_remove("IMPOSTER_PASS_MAC_KEY")
+        .env_remove("IMPOSTER_PASS_MAC_KEY_FILE")
+        .env_remove("IMPOSTER_PASS_SECURITY")
+        .stdout(Stdio::null());
+    cmd
+}
+
+fn insert(store: &Path, name: &str, value: &str) -> Child {
+    let mut child = imposter_pass(store)
+        .args(["insert", "--echo", "--force", name])
+        .stdin(Stdio::piped())
+        .spawn()
+        .unwrap();
+    writeln!(child.stdin.take().unwrap(), "{}", value).unwrap();
+    child
+}
+
+/// The value of each entry in the store file, leaving out what is known about them
+fn load(store: &Path) -> BTreeMap<String, String> {
+    let mut saved: serde_json::Value =
+        serde_json::from_reader(std::fs::File::open(store).unwrap()).unwrap();
+    // Stores are saved in whatever version of the format they were created in
+    if saved.get("version").is_some() {
+        saved = saved["entries"].take();
+    }
+    let saved: BTreeMap<String, serde_json::Value> = serde_json::from_value(saved).unwrap();
+
+    saved
+        .into_iter()
+        .map(|(name, entry)| {
+            let value = entry.get("value").unwrap_or(&entry);
+            (name, value.as_str().unwrap().to_string())
+        })
+        .collect()
+}
+
+#[test]
+fn concurrent_inserts_are_all_saved() {
+    let dir = test_dir("concurrent-inserts");
+    let store = dir.join("store.json");
+
+    let children: Vec<Child> = (0..PROCESSES)
+        .map(|i| insert(&store, &format!("entry/{}", i), &format!("password {}", i)))
+        .collect();
+    for mut child in children {
+        assert!(child.wait().unwrap().success());
+    }
+
+    let saved = load(&store);
+    for i in 0..PROCESSES {
+        assert_eq!(
+            saved.get(&format!("entry/{}", i)).map(String::as_str),
+            Some(format!("password {}\n", i).as_str()),
+            "entry/{} was lost",
+            i
+        );
+    }
+    assert_eq!(saved.len(), PROCESSES);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn concurrent_inserts_and_removes_are_all_saved() {
+    let dir = test_dir("concurrent-removes");
+    let store = dir.join("store.json");
+
+    let initial: BTreeMap<String, String> = (0..PROCESSES)
+        .map(|i| (format!("old/{}", i), "old\n".to_string()))
+        .collect();
+    std::fs::write(&store, serde_json::to_string(&initial).unwrap()).unwrap();
+
+    let children: Vec<Child> = (0..PROCESSES)
+        .flat_map(|i| {
+            let remove = imposter_pass(&store)
+                .args(["rm", "--force", &format!("old/{}", i)])
+                .spawn()
+                .unwrap();
+            let insert = insert(&store, &format!("new/{}", i), "new");
+            vec![remove, insert]
+        })
+        .collect();
+    for mut child in children {
+        assert!(child.wait().unwrap().success());
+    }
+
+    let saved = load(&store);
+    let expected: BTreeMap<String, String> = (0..PROCESSES)
+        .map(|i| (format!("new/{}", i), "new\n".to_string()))
+        .collect();
+    assert_eq!(saved, expected);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn a_held_lock_times_out() {
+    let dir = test_dir("lock-timeout");
+    let store = dir.join("store.json");
+
+    let lock = std::fs::File::create(dir.join("store.json.lock")).unwrap();
+    lock.lock().unwrap();
+
+    let output = imposter_pass(&store)
+        .args(["insert", "--echo", "--force", "entry"])
+        .env("IMPOSTER_PASS_LOCK_TIMEOUT", "0.2")
+        .stdin(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .and_then(|mut child| {
+            writeln!(child.stdin.take().unwrap(), "password")?;
+            child.wait_with_output()
+        })
+        .unwrap();
+
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("waiting for the lock"));
+    assert!(!store.exists());
+
+    drop(lock);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
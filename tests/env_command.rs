@@ -0,0 +1,34 @@
+//! Runs a command through `imposter-pass env`, and checks what ends up in its environment.
+
+use std::process::Command;
+
+#[test]
+fn the_command_only_gets_its_variables() {
+    let dir = std::env::temp_dir().join(format!("imposter-pass-env-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let policy = dir.join("policy.json");
+    std::fs::write(&policy, "{}").unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_imposter-pass"))
+        .args(["env", "--map", "DB_PASS=db/prod", "--", "env"])
+        .env("IMPOSTER_PASS_STORE", r#"{"db/prod": "hunter2"}"#)
+        .env("IMPOSTER_PASS_POLICY", &policy)
+        .env("IMPOSTER_PASS_QUIET", "true")
+        .env("IMPOSTER_PASS_SECURITY", "off")
+        .env_remove("IMPOSTER_PASS_SOCKET")
+        .env_remove("IMPOSTER_PASS_AUDIT_LOG")
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+
+    let env = String::from_utf8_lossy(&output.stdout);
+    assert!(env.lines().any(|line| line == "DB_PASS=hunter2"), "{}", env);
+    for var in &[
+        "IMPOSTER_PASS_STORE",
+        "IMPOSTER_PASS_POLICY",
+        "IMPOSTER_PASS_SECURITY",
+    ] {
+        assert!(!env.contains(var), "{} in {}", var, env);
+    }
+}
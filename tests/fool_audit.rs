@@ -0,0 +1,41 @@
+//! Fools a shell script with an audit log, and checks that its calls are logged wherever it goes.
+
+use std::path::PathBuf;
+use std::process::Command;
+
+/// A fresh directory for one test to keep its store in
+fn test_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("imposter-pass-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+#[test]
+fn relative_audit_logs_follow_the_program() {
+    let dir = test_dir("fool-audit");
+    std::fs::write(
+        dir.join("store.json"),
+        r#"{"version": 1, "entries": {"a": "1"}}"#,
+    )
+    .unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_imposter-pass"))
+        .args(["--store", "store.json", "fool", "--", "/bin/sh", "-c"])
+        .arg("cd / && pass show a")
+        .current_dir(&dir)
+        .env("IMPOSTER_PASS_QUIET", "true")
+        .env("IMPOSTER_PASS_AUDIT_LOG", "audit.log")
+        .env_remove("IMPOSTER_PASS_POLICY")
+        .env_remove("IMPOSTER_PASS_SOCKET")
+        .env_remove("IMPOSTER_PASS_STORE")
+        .env_remove("IMPOSTER_PASS_MAC_KEY")
+        .env_remove("IMPOSTER_PASS_MAC_KEY_FILE")
+        .env("IMPOSTER_PASS_SECURITY", "off")
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+
+    let log = std::fs::read_to_string(dir.join("audit.log")).unwrap();
+    assert!(log.contains(r#""subcommand":"show""#), "{}", log);
+}
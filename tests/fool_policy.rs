@@ -0,0 +1,146 @@
+//! Fools a shell script with a policy, and checks that it can't get at the hidden entries by any
+//! of the means it is given, and that they are still in the store afterwards.
+
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+/// A fresh directory for one test to keep its store in
+fn test_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("imposter-pass-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// Runs `script` under `fool` with `fool_args`, on a store with a hidden entry `secret/x`, and an
+/// entry `public/keep` that can't be removed
+fn fool(dir: &Path, fool_args: &[&str], script: &str) -> Output {
+    let store = dir.join("store.json");
+    std::fs::write(
+        &store,
+        r#"{"version": 1, "entries": {"public/keep": "kept", "public/x": "visible", "secret/x": "hidden"}}"#,
+    )
+    .unwrap();
+    let policy = dir.join("policy.json");
+    std::fs::write(
+        &policy,
+        r#"{"rules": [
+            {"prefix": "secret", "deny": ["show", "insert", "rm", "generate"]},
+            {"prefix": "public/keep", "deny": ["rm"]}
+        ]}"#,
+    )
+    .unwrap();
+    let script_path = dir.join("script.sh");
+    std::fs::write(&script_path, format!("#!/bin/sh\n{}", script)).unwrap();
+
+    Command::new(env!("CARGO_BIN_EXE_imposter-pass"))
+        .arg("--store")
+        .arg(&store)
+        .arg("fool")
+        .args(fool_args)
+        .arg("/bin/sh")
+        .arg(&script_path)
+        .env("IMPOSTER_PASS_QUIET", "true")
+        .env("IMPOSTER_PASS_POLICY", &policy)
+        .env_remove("IMPOSTER_PASS_AUDIT_LOG")
+        .env_remove("IMPOSTER_PASS_SOCKET")
+        .env_remove("IMPOSTER_PASS_STORE")
+        .env_remove("IMPOSTER_PASS_MAC_KEY")
+        .env_remove("IMPOSTER_PASS_MAC_KEY_FILE")
+        .env("IMPOSTER_PASS_SECURITY", "off")
+        .output()
+        .unwrap()
+}
+
+/// Everything the script could find out about the store
+const SNOOP: &str = r#"
+pass secret/x
+pass public/x
+[ -n "$IMPOSTER_PASS_STORE" ] && cat "$IMPOSTER_PASS_STORE"
+IMPOSTER_PASS_POLICY= pass secret/x
+pass rm -f secret/x
+pass rm -f public/x
+exit 0
+"#;
+
+fn check(dir: &Path, output: &Output) {
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "{:?}", output);
+    assert!(stdout.contains("visible"), "{}", stdout);
+    assert!(!stdout.contains("hidden"), "{}", stdout);
+
+    // The hidden entry is kept, while the change the script was allowed to make is saved
+    let saved = std::fs::read_to_string(dir.join("store.json")).unwrap();
+    assert!(saved.contains("hidden"), "{}", saved);
+    assert!(!saved.contains("public/x"), "{}", saved);
+}
+
+#[test]
+fn fooled_programs_cant_see_hidden_entries() {
+    let dir = test_dir("fool-policy");
+    let output = fool(&dir, &[], SNOOP);
+    check(&dir, &output);
+}
+
+#[test]
+fn served_programs_cant_see_hidden_entries() {
+    let dir = test_dir("fool-serve-policy");
+    let output = fool(&dir, &["--serve"], SNOOP);
+    check(&dir, &output);
+}
+
+/// Checks that the script failed without changing the store
+fn check_unchanged(dir: &Path, output: &Output) {
+    assert!(!output.status.success(), "{:?}", output);
+
+    let saved = std::fs::read_to_string(dir.join("store.json")).unwrap();
+    assert!(saved.contains("public/keep"), "{}", saved);
+    assert!(!saved.contains("hacked"), "{}", saved);
+}
+
+#[test]
+fn fooled_programs_cant_unset_the_policy() {
+    let dir = test_dir("fool-unset-policy");
+    let output = fool(
+        &dir,
+        &[],
+        "env -u IMPOSTER_PASS_POLICY pass rm -f public/keep",
+    );
+    check_unchanged(&dir, &output);
+
+    let output = fool(
+        &dir,
+        &[],
+        "echo hacked | env -u IMPOSTER_PASS_POLICY pass insert -e -f secret/y",
+    );
+    check_unchanged(&dir, &output);
+}
+
+#[test]
+fn fooled_programs_cant_unset_read_only() {
+    let dir = test_dir("fool-unset-read-only");
+    let output = fool(
+        &dir,
+        &["--read-only"],
+        "echo hacked | env -u IMPOSTER_PASS_READ_ONLY pass insert -e -f public/x",
+    );
+    check_unchanged(&dir, &output);
+
+    let output = fool(
+        &dir,
+        &["--read-only"],
+        r#"echo '{"version": 1, "entries": {"public/x": "hacked"}}' > "$IMPOSTER_PASS_STORE""#,
+    );
+    check_unchanged(&dir, &output);
+}
+
+#[test]
+fn served_programs_cant_unset_read_only() {
+    let dir = test_dir("fool-serve-unset-read-only");
+    let output = fool(
+        &dir,
+        &["--serve", "--read-only"],
+        "echo hacked | env -u IMPOSTER_PASS_READ_ONLY pass insert -e -f public/x",
+    );
+    check_unchanged(&dir, &output);
+}
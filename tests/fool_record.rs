@@ -0,0 +1,65 @@
+//! Records a shell script's calls to pass, and checks that recording doesn't change what the
+//! script sees.
+
+use serde_json::Value;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+/// A fresh directory for one test to keep its store in
+fn test_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("imposter-pass-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// Runs `script` under `fool --record`, and returns its output and the recorded session
+fn record(dir: &Path, script: &str) -> (Output, Value) {
+    let store = dir.join("store.json");
+    std::fs::write(&store, r#"{"version": 1, "entries": {"x": "1"}}"#).unwrap();
+    let session = dir.join("session.json");
+
+    let output = Command::new(env!("CARGO_BIN_EXE_imposter-pass"))
+        .arg("--store")
+        .arg(&store)
+        .arg("fool")
+        .arg("--record")
+        .arg(&session)
+        .args(["--", "/bin/sh", "-c", script])
+        .env("IMPOSTER_PASS_QUIET", "true")
+        .env_remove("IMPOSTER_PASS_AUDIT_LOG")
+        .env_remove("IMPOSTER_PASS_POLICY")
+        .env_remove("IMPOSTER_PASS_SOCKET")
+        .env_remove("IMPOSTER_PASS_STORE")
+        .env_remove("IMPOSTER_PASS_MAC_KEY")
+        .env_remove("IMPOSTER_PASS_MAC_KEY_FILE")
+        .env("IMPOSTER_PASS_SECURITY", "off")
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+
+    let session = serde_json::from_str(&std::fs::read_to_string(&session).unwrap()).unwrap();
+    (output, session)
+}
+
+#[test]
+fn recording_leaves_stdin_to_the_caller() {
+    let dir = test_dir("fool-record-stdin");
+    let (output, session) = record(
+        &dir,
+        r#"printf 'first\nsecond\n' | { pass show x; read line; echo "read $line"; }"#,
+    );
+
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "1read first\n");
+    assert_eq!(session["invocations"][0]["stdin"], "");
+}
+
+#[test]
+fn recording_keeps_the_input_of_commands_that_read_it() {
+    let dir = test_dir("fool-record-insert");
+    let (_, session) = record(&dir, "echo new | pass insert -e -f y");
+
+    assert_eq!(session["invocations"][0]["stdin"], "new\n");
+    let saved = std::fs::read_to_string(dir.join("store.json")).unwrap();
+    assert!(saved.contains("new"), "{}", saved);
+}
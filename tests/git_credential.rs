@@ -0,0 +1,141 @@
+//! Runs `git credential` with imposter-pass as its credential helper, and checks that git gets,
+//! saves and forgets credentials in the store.
+
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+
+/// A fresh directory for one test to keep its store in
+fn test_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("imposter-pass-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// Runs `git credential <action>` with `input` on stdin, using only the store in `dir`
+fn git_credential(dir: &Path, action: &str, input: &str) -> Output {
+    let helper = format!(
+        "!'{}' --store '{}' git-credential",
+        env!("CARGO_BIN_EXE_imposter-pass"),
+        dir.join("store.json").display()
+    );
+
+    let mut child = Command::new("git")
+        .arg("-c")
+        .arg(format!("credential.helper={}", helper))
+        .args(["credential", action])
+        .current_dir(dir)
+        // Keep the user's own git configuration and credential helpers out of it
+        .env("HOME", dir)
+        .env("GIT_CONFIG_NOSYSTEM", "1")
+        .env("GIT_CONFIG_GLOBAL", "/dev/null")
+        .env("GIT_TERMINAL_PROMPT", "0")
+        .env_remove("GIT_ASKPASS")
+        .env_remove("SSH_ASKPASS")
+        .env_remove("IMPOSTER_PASS_AUDIT_LOG")
+        .env_remove("IMPOSTER_PASS_POLICY")
+        .env_remove("IMPOSTER_PASS_SOCKET")
+        .env_remove("IMPOSTER_PASS_STORE")
+        .env_remove("IMPOSTER_PASS_MAC_KEY")
+        .env_remove("IMPOSTER_PASS_MAC_KEY_FILE")
+        .env("IMPOSTER_PASS_SECURITY", "off")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("git is installed");
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(input.as_bytes())
+        .unwrap();
+
+    child.wait_with_output().unwrap()
+}
+
+fn fill(dir: &Path, host: &str) -> Output {
+    git_credential(dir, "fill", &format!("protocol=https\nhost={}\n\n", host))
+}
+
+fn stdout(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stdout).into_owned()
+}
+
+#[test]
+fn fill_answers_from_the_store() {
+    let dir = test_dir("git-fill");
+    std::fs::write(
+        dir.join("store.json"),
+        r#"{"git/example.com/alice": "hunter2\nurl: https://example.com\n"}"#,
+    )
+    .unwrap();
+
+    let output = fill(&dir, "example.com");
+    assert!(output.status.success(), "{:?}", output);
+    assert_eq!(
+        stdout(&output),
+        "protocol=https\nhost=example.com\nusername=alice\npassword=hunter2\n"
+    );
+}
+
+#[test]
+fn approve_saves_and_reject_forgets() {
+    let dir = test_dir("git-approve-reject");
+    let credential = "protocol=https\nhost=example.org\nusername=bob\npassword=s3cret\n\n";
+
+    let output = git_credential(&dir, "approve", credential);
+    assert!(output.status.success(), "{:?}", output);
+    let saved = std::fs::read_to_string(dir.join("store.json")).unwrap();
+    assert!(saved.contains("git/example.org/bob"), "{}", saved);
+
+    let output = fill(&dir, "example.org");
+    assert!(output.status.success(), "{:?}", output);
+    assert!(
+        stdout(&output).contains("username=bob\npassword=s3cret\n"),
+        "{}",
+        stdout(&output)
+    );
+
+    let output = git_credential(&dir, "reject", credential);
+    assert!(output.status.success(), "{:?}", output);
+    let saved = std::fs::read_to_string(dir.join("store.json")).unwrap();
+    assert!(!saved.contains("git/example.org/bob"), "{}", saved);
+
+    // With nothing in the store and no terminal to ask on, git gives up
+    assert!(!fill(&dir, "example.org").status.success());
+}
+
+#[test]
+fn credentials_cant_reach_outside_their_folder() {
+    let dir = test_dir("git-escape");
+    let store = r#"{"db/prod": "topsecret", "git/evil.com/.hidden": "metadata"}"#;
+    std::fs::write(dir.join("store.json"), store).unwrap();
+
+    for credential in &[
+        "protocol=https\nhost=evil.com\nusername=/db/prod\n\n",
+        "protocol=https\nhost=evil.com\nusername=../../db/prod\n\n",
+        "protocol=https\nhost=..\nusername=db/prod\n\n",
+        "protocol=https\nhost=evil.com\npath=../..\nusername=db/prod\n\n",
+        "protocol=https\nhost=evil.com\nusername=.hidden\n\n",
+    ] {
+        let output = git_credential(&dir, "fill", credential);
+        assert!(
+            !stdout(&output).contains("topsecret"),
+            "{}",
+            stdout(&output)
+        );
+        assert!(!stdout(&output).contains("metadata"), "{}", stdout(&output));
+
+        let changed = format!("{}password=hacked\n\n", credential.trim_end());
+        git_credential(&dir, "approve", &changed);
+        git_credential(&dir, "reject", &changed);
+        assert_eq!(
+            std::fs::read_to_string(dir.join("store.json")).unwrap(),
+            store,
+            "{:?}",
+            credential
+        );
+    }
+}
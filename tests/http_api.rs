@@ -0,0 +1,176 @@
+//! Sends requests to `imposter-pass http`, and checks that the policy holds for every way of
+//! naming an entry in a URL.
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+
+const TOKEN: &str = "test-token";
+
+/// A fresh directory for one test to keep its store in
+fn test_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("imposter-pass-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// A server that is killed when it is dropped
+struct Server {
+    child: Child,
+    addr: String,
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+/// Serves `store` with a policy that hides everything under `secret`
+fn serve(dir: &Path, store: &str) -> Server {
+    let store_path = dir.join("store.json");
+    std::fs::write(&store_path, store).unwrap();
+    let policy_path = dir.join("policy.json");
+    std::fs::write(
+        &policy_path,
+        r#"{"rules": [{"prefix": "secret", "deny": ["show", "insert", "rm", "generate"]}]}"#,
+    )
+    .unwrap();
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_imposter-pass"))
+        .arg("--store")
+        .arg(&store_path)
+        .args(["http", "--listen", "127.0.0.1:0", "--token", TOKEN])
+        .env("IMPOSTER_PASS_QUIET", "true")
+        .env("IMPOSTER_PASS_POLICY", &policy_path)
+        .env_remove("IMPOSTER_PASS_AUDIT_LOG")
+        .env_remove("IMPOSTER_PASS_SOCKET")
+        .env_remove("IMPOSTER_PASS_MAC_KEY")
+        .env_remove("IMPOSTER_PASS_MAC_KEY_FILE")
+        .env_remove("IMPOSTER_PASS_SECURITY")
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+
+    let stdout = BufReader::new(child.stdout.take().unwrap());
+    let addr = stdout
+        .lines()
+        .map(Result::unwrap)
+        .find_map(|line| {
+            line.strip_prefix("Listening on http://")
+                .map(str::to_string)
+        })
+        .expect("the server never said where it listens");
+
+    Server { child, addr }
+}
+
+/// Sends a request exactly as given, and returns the status and the body of the response
+fn request(server: &Server, method: &str, path: &str, body: &str) -> (u16, String) {
+    let mut stream = TcpStream::connect(&server.addr).unwrap();
+    write!(
+        stream,
+        "{} {} HTTP/1.1\r\nHost: {}\r\nAuthorization: Bearer {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        method,
+        path,
+        server.addr,
+        TOKEN,
+        body.len(),
+        body
+    )
+    .unwrap();
+
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+
+    let status = response
+        .split(' ')
+        .nth(1)
+        .and_then(|status| status.parse().ok())
+        .expect("the response has no status");
+    let body = response
+        .split_once("\r\n\r\n")
+        .map(|(_, body)| body.to_string())
+        .unwrap_or_default();
+
+    (status, body)
+}
+
+#[test]
+fn shows_allowed_entries() {
+    let dir = test_dir("http-show");
+    let server = serve(&dir, r#"{"public/x": "visible", "secret/x": "hidden"}"#);
+
+    let (status, body) = request(&server, "GET", "/entries/public/x", "");
+    assert_eq!(status, 200);
+    assert!(body.contains("visible"), "{}", body);
+
+    let (status, body) = request(&server, "GET", "/entries", "");
+    assert_eq!(status, 200);
+    assert!(!body.contains("secret"), "{}", body);
+}
+
+#[test]
+fn denied_entries_stay_hidden_under_any_name() {
+    let dir = test_dir("http-denied");
+    let server = serve(&dir, r#"{"public/x": "visible", "secret/x": "hidden"}"#);
+
+    for path in &[
+        "/entries/secret/x",
+        "/entries/./secret/x",
+        "/entries/a/../secret/x",
+        "/entries/a/%2Fsecret/x",
+        "/entries/%2Fsecret/x",
+        "/entries/secret%2Fx",
+        "/entries/%2E/secret/x",
+        "/entries/public/%2E%2E/secret/x",
+        "/entries//secret/x",
+        "/entries/public//../secret/x",
+    ] {
+        let (status, body) = request(&server, "GET", path, "");
+        assert!(status == 400 || status == 403, "{} gave {}", path, status);
+        assert!(!body.contains("hidden"), "{} gave {}", path, body);
+    }
+}
+
+#[test]
+fn denied_entries_cant_be_changed_under_any_name() {
+    let dir = test_dir("http-denied-changes");
+    let server = serve(&dir, r#"{"secret/x": "hidden"}"#);
+
+    for path in &[
+        "/entries/./secret/y",
+        "/entries/a/%2Fsecret/y",
+        "/entries/%2E/secret/y",
+    ] {
+        let (status, _) = request(&server, "PUT", path, r#"{"value": "new"}"#);
+        assert!(
+            status == 400 || status == 403,
+            "PUT {} gave {}",
+            path,
+            status
+        );
+
+        let (status, _) = request(&server, "DELETE", path, "");
+        assert!(
+            status == 400 || status == 403,
+            "DELETE {} gave {}",
+            path,
+            status
+        );
+    }
+
+    let (status, _) = request(&server, "POST", "/generate/./secret/z", "");
+    assert!(status == 400 || status == 403, "generate gave {}", status);
+
+    let saved = std::fs::read_to_string(dir.join("store.json")).unwrap();
+    assert!(
+        !saved.contains("secret/y") && !saved.contains("secret/z"),
+        "{}",
+        saved
+    );
+}
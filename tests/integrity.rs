@@ -0,0 +1,78 @@
+//! Turns on MACs for an existing store, and checks that a tampered store is refused afterwards.
+
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+const KEY: &str = "test-key";
+
+/// A fresh directory for one test to keep its store in
+fn test_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("imposter-pass-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// Runs imposter-pass on the store in `dir`, with the MAC key set
+fn imposter_pass(dir: &Path, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_imposter-pass"))
+        .arg("--store")
+        .arg(dir.join("store.json"))
+        .args(args)
+        .env("IMPOSTER_PASS_QUIET", "true")
+        .env("IMPOSTER_PASS_MAC_KEY", KEY)
+        .env_remove("IMPOSTER_PASS_MAC_KEY_FILE")
+        .env_remove("IMPOSTER_PASS_AUDIT_LOG")
+        .env_remove("IMPOSTER_PASS_POLICY")
+        .env_remove("IMPOSTER_PASS_SOCKET")
+        .env("IMPOSTER_PASS_SECURITY", "off")
+        .output()
+        .unwrap()
+}
+
+fn stderr(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stderr).into_owned()
+}
+
+#[test]
+fn plain_stores_are_signed_and_then_checked() {
+    let dir = test_dir("integrity-sign");
+    let store = dir.join("store.json");
+    std::fs::write(&store, r#"{"version": 1, "entries": {"a": "real"}}"#).unwrap();
+
+    // Without a MAC, the store is refused until it is signed
+    let output = imposter_pass(&dir, &["show", "a"]);
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("no MAC"), "{}", stderr(&output));
+    assert!(!imposter_pass(&dir, &["verify"]).status.success());
+
+    let output = imposter_pass(&dir, &["verify", "--sign"]);
+    assert!(output.status.success(), "{:?}", output);
+    assert!(String::from_utf8_lossy(&output.stdout).contains("signed"));
+
+    assert!(imposter_pass(&dir, &["verify"]).status.success());
+    let output = imposter_pass(&dir, &["show", "a"]);
+    assert!(output.status.success(), "{:?}", output);
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "real");
+
+    // Signing again only checks the file
+    let output = imposter_pass(&dir, &["verify", "--sign"]);
+    assert!(output.status.success(), "{:?}", output);
+    assert!(String::from_utf8_lossy(&output.stdout).contains("OK"));
+
+    let signed = std::fs::read_to_string(&store).unwrap();
+    let tampered = signed.replace("real", "fake");
+    assert_ne!(signed, tampered);
+    std::fs::write(&store, &tampered).unwrap();
+
+    for args in &[&["show", "a"][..], &["verify"], &["verify", "--sign"]] {
+        let output = imposter_pass(&dir, args);
+        assert!(!output.status.success(), "{:?} gave {:?}", args, output);
+        assert!(
+            stderr(&output).contains("doesn't match"),
+            "{}",
+            stderr(&output)
+        );
+    }
+    assert_eq!(std::fs::read_to_string(&store).unwrap(), tampered);
+}
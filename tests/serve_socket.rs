@@ -0,0 +1,177 @@
+//! Talks to `imposter-pass serve` over its socket, and checks that clients only get to see and
+//! change what the policy allows.
+
+use serde_json::{json, Value};
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::time::{Duration, Instant};
+
+/// A fresh directory for one test to keep its store in
+fn test_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("imposter-pass-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// A server that is killed when it is dropped
+struct Server {
+    child: Child,
+    socket: PathBuf,
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+/// Serves `store` with a policy that hides everything under `secret`
+fn serve(dir: &Path, store: &str) -> Server {
+    let store_path = dir.join("store.json");
+    std::fs::write(&store_path, store).unwrap();
+    let policy_path = dir.join("policy.json");
+    std::fs::write(
+        &policy_path,
+        r#"{"rules": [{"prefix": "secret", "deny": ["show", "insert", "rm", "generate"]}]}"#,
+    )
+    .unwrap();
+    let socket = dir.join("store.sock");
+
+    let child = Command::new(env!("CARGO_BIN_EXE_imposter-pass"))
+        .arg("--store")
+        .arg(&store_path)
+        .arg("serve")
+        .arg("--socket")
+        .arg(&socket)
+        .env("IMPOSTER_PASS_QUIET", "true")
+        .env("IMPOSTER_PASS_POLICY", &policy_path)
+        .env_remove("IMPOSTER_PASS_AUDIT_LOG")
+        .env_remove("IMPOSTER_PASS_SOCKET")
+        .env_remove("IMPOSTER_PASS_MAC_KEY")
+        .env_remove("IMPOSTER_PASS_MAC_KEY_FILE")
+        .env_remove("IMPOSTER_PASS_SECURITY")
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+
+    let started = Instant::now();
+    while UnixStream::connect(&socket).is_err() {
+        assert!(
+            started.elapsed() < Duration::from_secs(10),
+            "the server never started"
+        );
+        std::thread::sleep(Duration::from_millis(10));
+    }
+
+    Server { child, socket }
+}
+
+fn request(server: &Server, request: Value) -> Value {
+    let mut stream = UnixStream::connect(&server.socket).unwrap();
+    writeln!(stream, "{}", request).unwrap();
+
+    let mut line = String::new();
+    BufReader::new(stream).read_line(&mut line).unwrap();
+    serde_json::from_str(&line).unwrap()
+}
+
+fn entries(server: &Server) -> Value {
+    let response = request(server, json!({ "request": "load" }));
+    assert_eq!(response["response"], "store", "{}", response);
+    response["store"]["entries"].clone()
+}
+
+#[test]
+fn only_owner_can_connect() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = test_dir("serve-mode");
+    let server = serve(&dir, r#"{"version": 1, "entries": {}}"#);
+
+    let mode = std::fs::metadata(&server.socket)
+        .unwrap()
+        .permissions()
+        .mode();
+    assert_eq!(mode & 0o777, 0o600);
+}
+
+#[test]
+fn load_hides_denied_entries() {
+    let dir = test_dir("serve-load");
+    let server = serve(
+        &dir,
+        r#"{"version": 1, "entries": {"public/x": "visible", "secret/x": "hidden"}}"#,
+    );
+
+    assert_eq!(entries(&server), json!({ "public/x": "visible" }));
+}
+
+#[test]
+fn clients_cant_shut_the_server_down() {
+    let dir = test_dir("serve-shutdown");
+    let server = serve(&dir, r#"{"version": 1, "entries": {"a": "1"}}"#);
+
+    for shutdown in &[
+        json!({ "request": "shutdown" }),
+        json!({ "request": "shutdown", "token": "" }),
+        json!({ "request": "shutdown", "token": "guess" }),
+    ] {
+        let response = request(&server, shutdown.clone());
+        assert_eq!(response["response"], "error", "{}", response);
+    }
+
+    assert_eq!(entries(&server), json!({ "a": "1" }));
+}
+
+#[test]
+fn silent_clients_dont_hold_up_the_others() {
+    let dir = test_dir("serve-silent");
+    let server = serve(&dir, r#"{"version": 1, "entries": {"a": "1"}}"#);
+
+    let _silent = UnixStream::connect(&server.socket).unwrap();
+    let started = Instant::now();
+    assert_eq!(entries(&server), json!({ "a": "1" }));
+    assert!(started.elapsed() < Duration::from_secs(30));
+}
+
+#[test]
+fn apply_checks_the_policy() {
+    let dir = test_dir("serve-apply");
+    let server = serve(
+        &dir,
+        r#"{"version": 1, "entries": {"public/x": "visible", "secret/x": "hidden"}}"#,
+    );
+
+    for diff in &[
+        json!({ "set": { "secret/y": "new" } }),
+        json!({ "set": { "./secret/y": "new" } }),
+        json!({ "removed": ["secret/x"] }),
+        json!({ "set": { "public/x": "changed" }, "removed": ["secret/x"] }),
+        json!({ "set": { "./public/z": "new" } }),
+    ] {
+        let response = request(&server, json!({ "request": "apply", "diff": diff }));
+        assert_eq!(response["response"], "error", "{} gave {}", diff, response);
+    }
+
+    let response = request(
+        &server,
+        json!({ "request": "apply", "diff": { "set": { "public/y": "new" } } }),
+    );
+    assert_eq!(response["response"], "ok", "{}", response);
+    let entries = entries(&server);
+    assert_eq!(entries["public/x"], "visible");
+    assert_eq!(entries["public/y"]["value"], "new");
+    assert!(entries.get("secret/x").is_none(), "{}", entries);
+
+    let saved = std::fs::read_to_string(dir.join("store.json")).unwrap();
+    assert!(
+        saved.contains("hidden") && !saved.contains("secret/y"),
+        "{}",
+        saved
+    );
+}